@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use chrono::Utc;
+use log::{error, info};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use tokio::process::Command;
+
+use crate::cfg::{AUDIO_CACHE_DIR, AUDIO_CACHE_MAX_BYTES};
+use crate::state::AudioCacheKey;
+use crate::store::{load_json, save_json};
+use crate::track::{TrackInfo, TrackSource};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    file: String,
+    size: u64,
+    last_used: i64,
+}
+
+/// Audio of played YouTube tracks kept on disk by video id, least recently
+/// played tracks are evicted once it grows past `AUDIO_CACHE_MAX_BYTES`.
+pub struct AudioCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    downloading: Mutex<HashSet<String>>,
+}
+
+fn index_file() -> String {
+    format!("{}/index.json", AUDIO_CACHE_DIR)
+}
+
+fn cache_path(file: &str) -> PathBuf {
+    Path::new(AUDIO_CACHE_DIR).join(file)
+}
+
+/// The video id of a YouTube link, checked so it's safe to use as a file name.
+pub fn video_id(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    let host = host
+        .strip_prefix("www.")
+        .or_else(|| host.strip_prefix("m."))
+        .or_else(|| host.strip_prefix("music."))
+        .unwrap_or(host);
+    let mut segments = url.path_segments()?;
+
+    let id = match (host, segments.next()) {
+        ("youtu.be", Some(id)) => id.to_string(),
+        ("youtube.com", Some("watch")) => url
+            .query_pairs()
+            .find(|(key, _)| key == "v")
+            .map(|(_, id)| id.to_string())?,
+        ("youtube.com", Some("shorts" | "embed" | "live")) => segments.next()?.to_string(),
+        _ => return None,
+    };
+
+    let valid = id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    valid.then_some(id)
+}
+
+impl AudioCache {
+    pub fn load() -> Self {
+        let mut entries: HashMap<String, CacheEntry> = load_json(&index_file());
+        entries.retain(|_, entry| cache_path(&entry.file).exists());
+
+        Self {
+            entries: Mutex::new(entries),
+            downloading: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Cached file for a track, marking it as recently used.
+    pub fn lookup(&self, track: &TrackInfo) -> Option<PathBuf> {
+        let TrackSource::Youtube(url) = &track.source else {
+            return None;
+        };
+        let id = video_id(url)?;
+
+        let path = {
+            let mut entries = self.entries.lock().ok()?;
+            let entry = entries.get_mut(&id)?;
+            let path = cache_path(&entry.file);
+
+            if !path.exists() {
+                entries.remove(&id);
+                return None;
+            }
+
+            entry.last_used = Utc::now().timestamp();
+            path
+        };
+
+        self.save();
+
+        Some(path)
+    }
+
+    /// Downloads a track's audio unless it's cached or already downloading.
+    pub async fn store(&self, track: &TrackInfo) {
+        let TrackSource::Youtube(url) = &track.source else {
+            return;
+        };
+        let Some(id) = video_id(url) else {
+            return;
+        };
+
+        if AUDIO_CACHE_MAX_BYTES == 0
+            || self
+                .entries
+                .lock()
+                .map(|e| e.contains_key(&id))
+                .unwrap_or(true)
+        {
+            return;
+        }
+
+        let started = self
+            .downloading
+            .lock()
+            .map(|mut downloading| downloading.insert(id.clone()))
+            .unwrap_or(false);
+        if !started {
+            return;
+        }
+
+        match download(&id, url).await {
+            Ok((file, size)) if size <= AUDIO_CACHE_MAX_BYTES => {
+                info!("Cached {} ({} bytes)", track.display(), size);
+
+                if let Ok(mut entries) = self.entries.lock() {
+                    entries.insert(
+                        id.clone(),
+                        CacheEntry {
+                            file,
+                            size,
+                            last_used: Utc::now().timestamp(),
+                        },
+                    );
+                    evict(&mut entries);
+                }
+                self.save();
+            }
+            Ok((file, _)) => {
+                let _ = fs::remove_file(cache_path(&file));
+            }
+            Err(e) => error!("Failed to cache {}: {:?}", url, e),
+        }
+
+        if let Ok(mut downloading) = self.downloading.lock() {
+            downloading.remove(&id);
+        }
+    }
+
+    fn save(&self) {
+        let Ok(entries) = self.entries.lock() else {
+            return;
+        };
+
+        if let Err(e) = save_json(&index_file(), &*entries) {
+            error!("Failed to save audio cache index: {:?}", e);
+        }
+    }
+}
+
+/// Drops least recently used files until the cache fits its size cap.
+fn evict(entries: &mut HashMap<String, CacheEntry>) {
+    let mut total = entries.values().map(|e| e.size).sum::<u64>();
+
+    while total > AUDIO_CACHE_MAX_BYTES {
+        let Some(oldest) = entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(id, _)| id.clone())
+        else {
+            break;
+        };
+
+        if let Some(entry) = entries.remove(&oldest) {
+            let _ = fs::remove_file(cache_path(&entry.file));
+            total -= entry.size;
+        }
+    }
+}
+
+/// Fetches the best audio-only format with yt-dlp, returning the file name and size.
+async fn download(id: &str, url: &str) -> Result<(String, u64), Error> {
+    fs::create_dir_all(AUDIO_CACHE_DIR)?;
+
+    let output = Command::new("yt-dlp")
+        .args(["-f", "bestaudio", "--no-playlist", "--no-warnings", "-o"])
+        .arg(format!("{}/{}.%(ext)s", AUDIO_CACHE_DIR, id))
+        .arg(url)
+        .output()
+        .await?;
+
+    let downloaded = fs::read_dir(AUDIO_CACHE_DIR)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(&format!("{}.", id)))
+        .collect::<Vec<_>>();
+
+    if !output.status.success() {
+        for name in downloaded {
+            let _ = fs::remove_file(cache_path(&name));
+        }
+
+        return Err(Error::msg(format!(
+            "yt-dlp download failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let file = downloaded
+        .into_iter()
+        .find(|name| !name.ends_with(".part") && !name.ends_with(".ytdl"))
+        .ok_or_else(|| Error::msg("yt-dlp didn't write a file"))?;
+    let size = fs::metadata(cache_path(&file))?.len();
+
+    Ok((file, size))
+}
+
+pub async fn get_audio_cache(ctx: &Context) -> Arc<AudioCache> {
+    let data = ctx.data.read().await;
+    data.get::<AudioCacheKey>()
+        .cloned()
+        .expect("Audio cache not found")
+}
@@ -2,15 +2,22 @@ use std::collections::HashMap;
 use std::env;
 use std::sync::{Arc, Mutex};
 
+use serenity::client::Context;
+
 use crate::history::History;
 use crate::openai::build_json_client;
+use crate::state::BotKey;
+use crate::stt::{build_speech_to_text, SpeechToText};
+use crate::tts::{build_text_to_speech, TextToSpeech};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Bot {
     pub history: Arc<Mutex<History>>,
     pub client: reqwest::Client,
     pub model: String,
     pub user_limits: Arc<Mutex<HashMap<u64, (i64, u64)>>>,
+    pub stt: Arc<dyn SpeechToText>,
+    pub tts: Arc<dyn TextToSpeech>,
 }
 
 impl Bot {
@@ -25,6 +32,13 @@ impl Bot {
             client,
             model,
             user_limits: Arc::new(Mutex::new(HashMap::new())),
+            stt: build_speech_to_text(),
+            tts: build_text_to_speech(),
         }
     }
 }
+
+pub async fn get_bot(ctx: &Context) -> Bot {
+    let data = ctx.data.read().await;
+    data.get::<BotKey>().cloned().expect("Bot not found")
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use log::error;
+use serenity::all::{ChannelId, GuildId};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+use serenity::utils::parse_channel_mention;
+use tokio::sync::mpsc;
+
+use crate::cfg::CAPTION_BATCH_SECS;
+use crate::dj::is_dj;
+use crate::guild::get_guild_store;
+
+/// Discord's message length limit.
+const MAX_MESSAGE_LEN: usize = 2000;
+
+/// Posts what's said in a guild's voice channel as text, a batch at a time so
+/// busy channels don't hit rate limits. Stops once dropped.
+pub struct Captions {
+    ctx: Context,
+    guild_id: GuildId,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl Captions {
+    pub fn start(ctx: Context, guild_id: GuildId) -> Self {
+        let (lines, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(post_batches(ctx.clone(), guild_id, receiver));
+
+        Self {
+            ctx,
+            guild_id,
+            lines,
+        }
+    }
+
+    /// Captions something said, unless captions are off or the speaker opted out.
+    /// `user_id` is `None` for the bot itself.
+    pub async fn add(&self, user_id: Option<u64>, name: &str, text: &str) {
+        let settings = get_guild_store(&self.ctx).await.get(self.guild_id.get());
+
+        let opted_out = user_id
+            .map(|user_id| settings.captions_opt_out.contains(&user_id))
+            .unwrap_or(false);
+
+        if settings.captions && !opted_out && !text.trim().is_empty() {
+            let _ = self.lines.send(format!("**{}**: {}", name, text.trim()));
+        }
+    }
+}
+
+/// Collects lines for `CAPTION_BATCH_SECS` after the first one arrives,
+/// then posts them all together.
+async fn post_batches(ctx: Context, guild_id: GuildId, mut lines: mpsc::UnboundedReceiver<String>) {
+    while let Some(first) = lines.recv().await {
+        tokio::time::sleep(Duration::from_secs(CAPTION_BATCH_SECS)).await;
+
+        let mut batch = vec![first];
+        while let Ok(line) = lines.try_recv() {
+            batch.push(line);
+        }
+
+        let Some(channel_id) = captions_channel(&ctx, guild_id).await else {
+            continue;
+        };
+
+        for content in split_messages(&batch) {
+            let message = CreateMessage::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new());
+
+            if let Err(e) = channel_id.send_message(&ctx.http, message).await {
+                error!("Failed to post captions: {:?}", e);
+            }
+        }
+    }
+}
+
+/// The configured captions channel, or the text chat of the voice channel the bot is in.
+async fn captions_channel(ctx: &Context, guild_id: GuildId) -> Option<ChannelId> {
+    let settings = get_guild_store(ctx).await.get(guild_id.get());
+
+    if !settings.captions {
+        return None;
+    }
+
+    if let Some(channel_id) = settings.captions_channel {
+        return Some(ChannelId::new(channel_id));
+    }
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let handler_lock = manager.get(guild_id)?;
+    let channel_id = handler_lock.lock().await.current_channel()?;
+
+    Some(ChannelId::new(channel_id.0.get()))
+}
+
+/// Joins lines into as few messages as fit under Discord's length limit.
+fn split_messages(lines: &[String]) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+
+    for line in lines {
+        let line = match line.char_indices().nth(MAX_MESSAGE_LEN - 1) {
+            Some((end, _)) => &line[..end],
+            None => line.as_str(),
+        };
+
+        if !current.is_empty() && current.len() + line.len() + 1 > MAX_MESSAGE_LEN {
+            messages.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+/// Turns live captions on or off, and lets anyone keep their own speech out of them.
+#[command]
+#[only_in(guilds)]
+#[aliases("cc")]
+#[description = "Posts what's said in voice: ~captions on [#channel], ~captions off, ~captions optout, ~captions optin"]
+pub async fn captions(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let user_id = msg.author.id.get();
+    let store = get_guild_store(ctx).await;
+
+    let option = args.single::<String>().unwrap_or_default().to_lowercase();
+    let channel = args.single::<String>().ok();
+
+    let text = match option.as_str() {
+        "" => {
+            let settings = store.get(guild_id.get());
+            match (settings.captions, settings.captions_channel) {
+                (false, _) => "Captions are off.".to_string(),
+                (true, Some(channel_id)) => format!("Captions are posted to <#{}>.", channel_id),
+                (true, None) => "Captions are posted to the voice channel's chat.".to_string(),
+            }
+        }
+        "optout" | "opt-out" => {
+            store.update(guild_id.get(), |s| {
+                s.captions_opt_out.insert(user_id);
+            });
+            "Your speech won't be captioned.".to_string()
+        }
+        "optin" | "opt-in" => {
+            store.update(guild_id.get(), |s| {
+                s.captions_opt_out.remove(&user_id);
+            });
+            "Your speech will be captioned.".to_string()
+        }
+        "on" | "off" if !is_dj(ctx, guild_id, msg.author.id).await => {
+            "Only DJs can turn captions on or off.".to_string()
+        }
+        "on" => {
+            let channel_id = match channel.as_deref().map(parse_channel_mention) {
+                Some(None) => {
+                    let _ = msg
+                        .channel_id
+                        .say(&ctx.http, "Usage: ~captions on [#channel]")
+                        .await;
+                    return Ok(());
+                }
+                Some(Some(channel_id)) => Some(channel_id.get()),
+                None => None,
+            };
+
+            store.update(guild_id.get(), |s| {
+                s.captions = true;
+                s.captions_channel = channel_id;
+            });
+
+            match channel_id {
+                Some(channel_id) => format!("Captions will be posted to <#{}>.", channel_id),
+                None => "Captions will be posted to the voice channel's chat.".to_string(),
+            }
+        }
+        "off" => {
+            store.update(guild_id.get(), |s| s.captions = false);
+            "Captions off.".to_string()
+        }
+        _ => "Usage: ~captions on [#channel], ~captions off, ~captions optout, ~captions optin"
+            .to_string(),
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
@@ -1,8 +1,118 @@
 pub const LOG_FILE: &str = "output.log";
 
+pub const MUSIC_DIR: &str = "music";
+
+/// Seconds with an empty queue and nobody talking before leaving voice.
+pub const IDLE_TIMEOUT_SECS: u64 = 300;
+pub const WATCHDOG_INTERVAL_SECS: u64 = 30;
+
+/// Fraction of listeners in the channel needed to vote a track off.
+pub const SKIP_VOTE_RATIO: f32 = 0.5;
+
+pub const GUILDS_FILE: &str = "data/guilds.json";
+pub const SESSIONS_DIR: &str = "data/sessions";
+pub const PLAYLISTS_FILE: &str = "data/playlists.json";
+pub const PLAYS_DIR: &str = "data/plays";
+pub const SOUNDBOARD_DIR: &str = "data/soundboard";
+/// Downloaded audio for replaying tracks without going back to YouTube.
+pub const AUDIO_CACHE_DIR: &str = "data/audio";
+/// Size the audio cache is kept under by evicting least recently played tracks.
+pub const AUDIO_CACHE_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Plays kept in each guild's history, older ones are dropped.
+pub const PLAY_HISTORY_LIMIT: usize = 1000;
+
+/// Track volume for guilds that haven't set one, 1.0 is full volume.
+pub const DEFAULT_VOLUME: f32 = 0.05;
+
 pub const BOT_ID: u64 = 1179957141688291498;
 
 pub const SYS_PROMPT: &str = "You will be receiving messages in the format: 'username: message'.
 If you see a username 'adam' in the conversation history, that was you.
 Do not respond in the this format, only respond with responses.
 If you're unable to respond to something, respond in an ominous manner.";
+
+/// Volume of the bot's spoken replies.
+pub const TTS_VOLUME: f32 = 0.5;
+/// Fraction of the music volume kept while the bot is speaking.
+pub const DUCK_LEVEL: f32 = 0.3;
+pub const DUCK_FADE_MS: u64 = 250;
+
+/// Integrated loudness that normalized tracks are brought toward, in LUFS.
+pub const NORMALIZE_TARGET_LUFS: f64 = -14.0;
+/// Seconds of audio measured before a normalized track starts playing.
+pub const NORMALIZE_ANALYSIS_SECS: f64 = 10.0;
+pub const NORMALIZE_MIN_GAIN: f32 = 0.1;
+pub const NORMALIZE_MAX_GAIN: f32 = 4.0;
+
+/// Low shelf gain and corner frequency of the bass boost filter.
+pub const BASS_BOOST_DB: f64 = 6.0;
+pub const BASS_BOOST_HZ: f64 = 120.0;
+/// Playback speed of the nightcore filter, pitch rises along with it.
+pub const NIGHTCORE_SPEED: f64 = 1.25;
+/// Seconds the 8D filter takes to pan from one side to the other and back.
+pub const EIGHT_D_PERIOD_SECS: f64 = 8.0;
+/// Peak level the limiter keeps samples under, about -1 dBFS.
+pub const LIMITER_CEILING: f32 = 0.89;
+pub const LIMITER_RELEASE_MS: f32 = 100.0;
+
+/// Recently played tracks autoplay bases its picks on.
+pub const RADIO_SEED_TRACKS: usize = 5;
+/// Tracks autoplay won't pick again until this many others have played.
+pub const RADIO_REPEAT_WINDOW: usize = 25;
+pub const RADIO_SUGGESTIONS: usize = 5;
+pub const RADIO_PROMPT: &str = "You are a radio DJ picking what plays next.
+Given the songs that were just played, suggest different songs that fit well after them.
+Reply with one song per line formatted as 'Artist - Title', and nothing else.";
+
+/// Longest track that can be queued, in seconds.
+pub const MAX_TRACK_SECS: u64 = 60 * 60;
+/// Tracks a single member can have waiting in the queue at once.
+pub const MAX_USER_TRACKS: usize = 10;
+pub const MAX_GUILD_TRACKS: usize = 100;
+
+/// Longest and largest clip the soundboard accepts.
+pub const SOUNDBOARD_MAX_SECS: f64 = 10.0;
+pub const SOUNDBOARD_MAX_BYTES: u32 = 1024 * 1024;
+pub const SOUNDBOARD_MAX_CLIPS: usize = 50;
+
+/// Level a speaker's audio has to reach to count as speech, in dBFS.
+pub const VAD_THRESHOLD_DB: f32 = -50.0;
+/// Silence that ends an utterance.
+pub const VAD_HANGOVER_MS: u32 = 600;
+/// Utterances shorter than this are dropped as noise.
+pub const UTTERANCE_MIN_MS: u32 = 300;
+/// Utterances are cut at this length even if the speaker keeps going.
+pub const UTTERANCE_MAX_MS: u32 = 15_000;
+/// Finished utterances waiting for the voice worker before new ones are dropped.
+pub const VOICE_QUEUE_CAPACITY: usize = 4;
+
+/// Keeps a copy of every transcribed utterance in `RECORDINGS_DIR`, for debugging.
+pub const RECORD_UTTERANCES: bool = false;
+pub const RECORDINGS_DIR: &str = "data/recordings";
+
+/// Language hint for transcription, e.g. `Some("en")`, detected when `None`.
+pub const STT_LANGUAGE: Option<&str> = None;
+/// Words transcription should expect, like the bot's name.
+pub const STT_PROMPT: Option<&str> = None;
+
+/// Default text to speech settings, changed per guild with `~voice`.
+pub const TTS_MODEL: &str = "tts-1";
+pub const TTS_VOICE: &str = "onyx";
+pub const TTS_SPEED: f32 = 1.0;
+pub const TTS_FORMAT: &str = "mp3";
+/// Voices `~voice` accepts, any name is allowed when empty (e.g. for a local server).
+pub const TTS_VOICES: &[&str] = &[
+    "alloy", "ash", "coral", "echo", "fable", "onyx", "nova", "sage", "shimmer",
+];
+
+/// How long someone has to talk over a reply to cut it off, when `~bargein` is on.
+pub const BARGE_IN_MS: u32 = 800;
+
+/// Names the bot answers to when spoken, matched loosely since transcription
+/// mangles them. Aliases under 4 letters have to be heard exactly.
+pub const WAKE_WORDS: &[&str] = &["adam", "add", "i don't"];
+/// How much "volume up" and "volume down" change the volume by.
+pub const VOICE_VOLUME_STEP: f32 = 0.2;
+
+/// Live captions are collected for this long before being posted together.
+pub const CAPTION_BATCH_SECS: u64 = 3;
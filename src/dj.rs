@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use serenity::all::{GuildId, Permissions, RoleId, UserId};
+use serenity::client::Context;
+use songbird::tracks::TrackHandle;
+
+use crate::cfg::SKIP_VOTE_RATIO;
+use crate::guild::get_guild_store;
+use crate::state::SkipVotesKey;
+use crate::track::TrackInfoKey;
+use crate::voice::channel_listeners;
+
+/// Votes to skip the track currently playing in a guild.
+#[derive(Default)]
+pub struct SkipVotes {
+    track: Option<TrackHandle>,
+    voters: HashSet<u64>,
+}
+
+pub enum SkipOutcome {
+    Skipped,
+    Voted { votes: usize, needed: usize },
+    AlreadyVoted { votes: usize, needed: usize },
+    NotListening,
+    NothingPlaying,
+}
+
+/// Whether a member may use destructive music controls. Guilds without a
+/// DJ role leave everyone in charge, admins are always DJs.
+pub async fn is_dj(ctx: &Context, guild_id: GuildId, user_id: UserId) -> bool {
+    let Some(dj_role) = get_guild_store(ctx).await.get(guild_id.get()).dj_role else {
+        return true;
+    };
+
+    let Ok(member) = guild_id.member(ctx, user_id).await else {
+        return false;
+    };
+
+    if member.roles.contains(&RoleId::new(dj_role)) {
+        return true;
+    }
+
+    ctx.cache
+        .guild(guild_id)
+        .map(|guild| {
+            let permissions = guild.member_permissions(&member);
+            guild.owner_id == user_id
+                || permissions.contains(Permissions::ADMINISTRATOR)
+                || permissions.contains(Permissions::MANAGE_GUILD)
+        })
+        .unwrap_or(false)
+}
+
+async fn requested(track: &TrackHandle, user_id: UserId) -> bool {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<TrackInfoKey>()
+        .map(|info| info.requester == user_id.get())
+        .unwrap_or(false)
+}
+
+/// Skips the current track for DJs and its requester, otherwise counts a
+/// vote and skips once enough of the channel agrees.
+pub async fn vote_skip(ctx: &Context, guild_id: GuildId, user_id: UserId) -> SkipOutcome {
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    let Some(handler_lock) = manager.get(guild_id) else {
+        return SkipOutcome::NothingPlaying;
+    };
+
+    let (current, channel_id) = {
+        let handler = handler_lock.lock().await;
+        (handler.queue().current(), handler.current_channel())
+    };
+
+    let Some(current) = current else {
+        return SkipOutcome::NothingPlaying;
+    };
+
+    if is_dj(ctx, guild_id, user_id).await || requested(&current, user_id).await {
+        let _ = handler_lock.lock().await.queue().skip();
+        return SkipOutcome::Skipped;
+    }
+
+    let listeners = channel_id
+        .map(|channel_id| channel_listeners(ctx, guild_id, channel_id.0.get()))
+        .unwrap_or_default();
+
+    if !listeners.contains(&user_id) {
+        return SkipOutcome::NotListening;
+    }
+
+    let needed = ((listeners.len() as f32 * SKIP_VOTE_RATIO).ceil() as usize).max(1);
+
+    let (votes, new_vote) = {
+        let data = ctx.data.read().await;
+        let all_votes = data.get::<SkipVotesKey>().expect("Skip votes not found");
+        let mut votes = all_votes.entry(guild_id.get()).or_default();
+
+        if votes.track.as_ref().map(|t| t.uuid()) != Some(current.uuid()) {
+            votes.track = Some(current.clone());
+            votes.voters.clear();
+        }
+
+        // Only count votes from people still listening.
+        votes
+            .voters
+            .retain(|voter| listeners.contains(&UserId::new(*voter)));
+        let new_vote = votes.voters.insert(user_id.get());
+
+        (votes.voters.len(), new_vote)
+    };
+
+    if votes >= needed {
+        let _ = handler_lock.lock().await.queue().skip();
+        return SkipOutcome::Skipped;
+    }
+
+    if new_vote {
+        SkipOutcome::Voted { votes, needed }
+    } else {
+        SkipOutcome::AlreadyVoted { votes, needed }
+    }
+}
+
+impl SkipOutcome {
+    pub fn describe(&self) -> String {
+        match self {
+            SkipOutcome::Skipped => "Song skipped.".to_string(),
+            SkipOutcome::Voted { votes, needed } => {
+                format!("Vote to skip: {}/{}", votes, needed)
+            }
+            SkipOutcome::AlreadyVoted { votes, needed } => {
+                format!("You already voted: {}/{}", votes, needed)
+            }
+            SkipOutcome::NotListening => "You're not in the voice channel.".to_string(),
+            SkipOutcome::NothingPlaying => "Nothing playing.".to_string(),
+        }
+    }
+}
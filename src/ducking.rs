@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serenity::all::GuildId;
+use serenity::async_trait;
+use serenity::client::Context;
+use songbird::input::Input;
+use songbird::tracks::TrackHandle;
+use songbird::{Call, Event, EventContext as Ctx, EventHandler, TrackEvent};
+
+use crate::cfg::{DUCK_FADE_MS, DUCK_LEVEL, TTS_VOLUME};
+use crate::guild::get_guild_store;
+use crate::state::DuckingKey;
+
+const FADE_STEPS: u64 = 10;
+
+/// Music level for a guild, lowered while the bot is talking over it.
+pub struct Ducking {
+    speaking: AtomicUsize,
+    level: Mutex<f32>,
+    /// Bumped by every fade, so an older fade stops once a newer one starts.
+    generation: AtomicU64,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            speaking: AtomicUsize::new(0),
+            level: Mutex::new(1.0),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Ducking {
+    pub fn level(&self) -> f32 {
+        self.level.lock().map(|level| *level).unwrap_or(1.0)
+    }
+
+    fn set_level(&self, level: f32) {
+        if let Ok(mut current) = self.level.lock() {
+            *current = level;
+        }
+    }
+
+    /// Forgets any clips still speaking and stops running fades, used when
+    /// leaving voice so music isn't ducked next time the bot joins.
+    pub fn reset(&self) {
+        self.speaking.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.set_level(1.0);
+    }
+}
+
+pub async fn get_ducking(ctx: &Context, guild_id: GuildId) -> Arc<Ducking> {
+    let data = ctx.data.read().await;
+    let ducking = data
+        .get::<DuckingKey>()
+        .expect("Ducking state not found")
+        .entry(guild_id.get())
+        .or_default()
+        .clone();
+
+    ducking
+}
+
+/// Volume queued music should currently play at in a guild.
+pub async fn music_volume(ctx: &Context, guild_id: GuildId) -> f32 {
+    let volume = get_guild_store(ctx).await.get(guild_id.get()).volume;
+
+    volume * get_ducking(ctx, guild_id).await.level()
+}
+
+/// Plays a spoken clip over the queue, ducking music until it ends.
+pub async fn speak(
+    ctx: &Context,
+    guild_id: GuildId,
+    handler: &mut Call,
+    input: Input,
+) -> TrackHandle {
+    let ducking = get_ducking(ctx, guild_id).await;
+
+    let handle = handler.play_input(input);
+    let _ = handle.set_volume(TTS_VOLUME);
+
+    let restore = Unduck {
+        ctx: ctx.clone(),
+        guild_id,
+        done: Arc::new(AtomicUsize::new(0)),
+    };
+    let _ = handle.add_event(Event::Track(TrackEvent::End), restore.clone());
+    let _ = handle.add_event(Event::Track(TrackEvent::Error), restore);
+
+    if ducking.speaking.fetch_add(1, Ordering::SeqCst) == 0 {
+        fade(
+            ctx.clone(),
+            guild_id,
+            handler.queue().current_queue(),
+            DUCK_LEVEL,
+        );
+    }
+
+    handle
+}
+
+/// Restores music once a spoken clip ends or fails, whichever fires first.
+#[derive(Clone)]
+struct Unduck {
+    ctx: Context,
+    guild_id: GuildId,
+    done: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl EventHandler for Unduck {
+    async fn act(&self, _ctx: &Ctx<'_>) -> Option<Event> {
+        if self.done.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Some(Event::Cancel);
+        }
+
+        let ducking = get_ducking(&self.ctx, self.guild_id).await;
+
+        // Clips that were speaking when the bot left have already been reset.
+        let speaking = ducking
+            .speaking
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+
+        if speaking == Ok(1) {
+            let manager = songbird::get(&self.ctx).await.unwrap().clone();
+
+            if let Some(handler_lock) = manager.get(self.guild_id) {
+                let tracks = handler_lock.lock().await.queue().current_queue();
+                fade(self.ctx.clone(), self.guild_id, tracks, 1.0);
+            }
+        }
+
+        Some(Event::Cancel)
+    }
+}
+
+/// Ramps queue tracks to `target` times the guild volume over `DUCK_FADE_MS`.
+fn fade(ctx: Context, guild_id: GuildId, tracks: Vec<TrackHandle>, target: f32) {
+    tokio::spawn(async move {
+        let ducking = get_ducking(&ctx, guild_id).await;
+        let volume = get_guild_store(&ctx).await.get(guild_id.get()).volume;
+
+        let generation = ducking.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let start = ducking.level();
+
+        for step in 1..=FADE_STEPS {
+            if ducking.generation.load(Ordering::SeqCst) != generation {
+                return;
+            }
+
+            let level = start + (target - start) * step as f32 / FADE_STEPS as f32;
+            ducking.set_level(level);
+
+            for track in &tracks {
+                let _ = track.set_volume(volume * level);
+            }
+
+            tokio::time::sleep(Duration::from_millis(DUCK_FADE_MS / FADE_STEPS)).await;
+        }
+    });
+}
@@ -0,0 +1,411 @@
+use std::f64::consts::{PI, SQRT_2, TAU};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::{
+    BASS_BOOST_DB, BASS_BOOST_HZ, EIGHT_D_PERIOD_SECS, LIMITER_CEILING, LIMITER_RELEASE_MS,
+    NIGHTCORE_SPEED,
+};
+use crate::loudness::Biquad;
+use crate::pcm::Stage;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    Pop,
+    Rock,
+    Vocal,
+    Soft,
+}
+
+impl EqPreset {
+    pub const ALL: [EqPreset; 5] = [
+        EqPreset::Flat,
+        EqPreset::Pop,
+        EqPreset::Rock,
+        EqPreset::Vocal,
+        EqPreset::Soft,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EqPreset::Flat => "flat",
+            EqPreset::Pop => "pop",
+            EqPreset::Rock => "rock",
+            EqPreset::Vocal => "vocal",
+            EqPreset::Soft => "soft",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// Gain in dB for each of the `EQ_BANDS`.
+    fn gains(&self) -> [f64; 5] {
+        match self {
+            EqPreset::Flat => [0.0; 5],
+            EqPreset::Pop => [-1.0, 2.0, 3.0, 1.0, -1.0],
+            EqPreset::Rock => [4.0, -2.0, -1.0, 3.0, 4.0],
+            EqPreset::Vocal => [-3.0, -1.0, 3.0, 4.0, 0.0],
+            EqPreset::Soft => [1.0, 0.0, -2.0, -3.0, -4.0],
+        }
+    }
+}
+
+/// Centre frequencies of the equalizer's bands.
+const EQ_BANDS: [f64; 5] = [60.0, 250.0, 1000.0, 4000.0, 12000.0];
+
+/// Effects a guild has enabled for its music.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    pub bass_boost: bool,
+    pub eq: EqPreset,
+    pub nightcore: bool,
+    pub eight_d: bool,
+    pub limiter: bool,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        *self == Filters::default()
+    }
+
+    /// The on/off filter called `name`, as used by the `~filter` command.
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "bass" | "bassboost" => Some(&mut self.bass_boost),
+            "nightcore" => Some(&mut self.nightcore),
+            "8d" => Some(&mut self.eight_d),
+            "limiter" => Some(&mut self.limiter),
+            _ => None,
+        }
+    }
+
+    /// Playback speed relative to the source.
+    pub fn speed(&self) -> f64 {
+        if self.nightcore {
+            NIGHTCORE_SPEED
+        } else {
+            1.0
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut enabled = Vec::new();
+
+        if self.bass_boost {
+            enabled.push("bass boost".to_string());
+        }
+        if self.eq != EqPreset::Flat {
+            enabled.push(format!("eq {}", self.eq.name()));
+        }
+        if self.nightcore {
+            enabled.push("nightcore".to_string());
+        }
+        if self.eight_d {
+            enabled.push("8d".to_string());
+        }
+        if self.limiter {
+            enabled.push("limiter".to_string());
+        }
+
+        if enabled.is_empty() {
+            "none".to_string()
+        } else {
+            enabled.join(", ")
+        }
+    }
+
+    /// Stages shaping the tone, run before normalization so it can even out
+    /// any change in loudness.
+    pub fn tone_stages(&self, rate: u32, channels: usize) -> Vec<Box<dyn Stage>> {
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+        let rate = rate as f64;
+
+        if self.eq != EqPreset::Flat {
+            let bands = EQ_BANDS
+                .iter()
+                .zip(self.eq.gains())
+                // Bands past the Nyquist frequency can't be represented.
+                .filter(|(&freq, gain)| *gain != 0.0 && freq < rate * 0.45)
+                .map(|(&freq, gain)| peaking(rate, freq, gain, 1.0))
+                .collect();
+            stages.push(Box::new(FilterBank::new(channels, bands)));
+        }
+
+        if self.bass_boost {
+            let shelf = low_shelf(rate, BASS_BOOST_HZ, BASS_BOOST_DB);
+            stages.push(Box::new(FilterBank::new(channels, vec![shelf])));
+        }
+
+        stages
+    }
+
+    /// Stages run last, right before the audio is handed to songbird.
+    pub fn output_stages(&self, rate: u32, channels: usize) -> Vec<Box<dyn Stage>> {
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+
+        if self.eight_d && channels >= 2 {
+            stages.push(Box::new(AutoPan::new(rate, channels)));
+        }
+
+        if self.limiter {
+            stages.push(Box::new(Limiter::new(rate, channels)));
+        }
+
+        stages
+    }
+}
+
+/// Peaking EQ band from the RBJ audio EQ cookbook.
+fn peaking(rate: f64, freq: f64, gain_db: f64, q: f64) -> Biquad {
+    let a = 10f64.powf(gain_db / 40.0);
+    let w0 = TAU * freq / rate;
+    let alpha = w0.sin() / (2.0 * q);
+    let cos = w0.cos();
+
+    Biquad::new(
+        [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+        [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+    )
+}
+
+/// Low shelf with a slope of 1 from the RBJ audio EQ cookbook.
+fn low_shelf(rate: f64, freq: f64, gain_db: f64) -> Biquad {
+    let a = 10f64.powf(gain_db / 40.0);
+    let w0 = TAU * freq / rate;
+    let alpha = w0.sin() / SQRT_2;
+    let cos = w0.cos();
+    let sqrt_a = a.sqrt();
+
+    Biquad::new(
+        [
+            a * ((a + 1.0) - (a - 1.0) * cos + 2.0 * sqrt_a * alpha),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - 2.0 * sqrt_a * alpha),
+        ],
+        [
+            (a + 1.0) + (a - 1.0) * cos + 2.0 * sqrt_a * alpha,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - 2.0 * sqrt_a * alpha,
+        ],
+    )
+}
+
+/// Runs every channel through the same chain of biquads.
+pub struct FilterBank {
+    channels: usize,
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl FilterBank {
+    pub fn new(channels: usize, chain: Vec<Biquad>) -> Self {
+        let channels = channels.max(1);
+
+        Self {
+            channels,
+            filters: vec![chain; channels],
+        }
+    }
+}
+
+impl Stage for FilterBank {
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (sample, chain) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                let mut x = *sample as f64;
+                for filter in chain.iter_mut() {
+                    x = filter.process(x);
+                }
+                *sample = x as f32;
+            }
+        }
+    }
+}
+
+/// Slowly pans the first two channels around the listener, the "8D" effect.
+pub struct AutoPan {
+    channels: usize,
+    phase: f64,
+    step: f64,
+}
+
+impl AutoPan {
+    pub fn new(rate: u32, channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            phase: 0.0,
+            step: TAU / (EIGHT_D_PERIOD_SECS * rate.max(1) as f64),
+        }
+    }
+}
+
+impl Stage for AutoPan {
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.channels < 2 {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(self.channels) {
+            // Constant power pan, unity gain when centred.
+            let angle = (self.phase.sin() + 1.0) * PI / 4.0;
+            frame[0] *= (angle.cos() * SQRT_2) as f32;
+            frame[1] *= (angle.sin() * SQRT_2) as f32;
+
+            self.phase = (self.phase + self.step) % TAU;
+        }
+    }
+}
+
+/// Keeps peaks under `LIMITER_CEILING`, clamping gain instantly and letting
+/// it recover over `LIMITER_RELEASE_MS`.
+pub struct Limiter {
+    channels: usize,
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(rate: u32, channels: usize) -> Self {
+        let release_frames = LIMITER_RELEASE_MS / 1000.0 * rate.max(1) as f32;
+
+        Self {
+            channels: channels.max(1),
+            gain: 1.0,
+            release: 1.0 - (-1.0 / release_frames).exp(),
+        }
+    }
+}
+
+impl Stage for Limiter {
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            self.gain += (1.0 - self.gain) * self.release;
+
+            let peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            if peak * self.gain > LIMITER_CEILING {
+                self.gain = LIMITER_CEILING / peak;
+            }
+
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Interleaved stereo sine with the same signal in both channels.
+    fn stereo_sine(freq: f64, dbfs: f64, secs: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+
+        (0..(RATE as f64 * secs) as usize)
+            .map(|i| (amplitude * (TAU * freq * i as f64 / RATE as f64).sin()) as f32)
+            .flat_map(|s| [s, s])
+            .collect()
+    }
+
+    fn run(stages: &mut [Box<dyn Stage>], samples: &mut [f32]) {
+        for chunk in samples.chunks_mut(1920) {
+            for stage in stages.iter_mut() {
+                stage.process(chunk);
+            }
+        }
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    /// Change in level in dB the tone stages make to a sine, once settled.
+    fn tone_gain_db(filters: &Filters, freq: f64) -> f64 {
+        let input = stereo_sine(freq, -20.0, 2.0);
+        let mut output = input.clone();
+        run(&mut filters.tone_stages(RATE, 2), &mut output);
+
+        let settled = input.len() / 2;
+        20.0 * (rms(&output[settled..]) / rms(&input[settled..])).log10()
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_ceiling() {
+        let filters = Filters {
+            limiter: true,
+            ..Filters::default()
+        };
+
+        let mut samples = stereo_sine(1000.0, 6.0, 1.0);
+        run(&mut filters.output_stages(RATE, 2), &mut samples);
+
+        let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= LIMITER_CEILING + 1e-6, "{}", peak);
+    }
+
+    #[test]
+    fn bass_boost_only_raises_lows() {
+        let filters = Filters {
+            bass_boost: true,
+            ..Filters::default()
+        };
+
+        let low = tone_gain_db(&filters, 60.0);
+        let high = tone_gain_db(&filters, 5000.0);
+
+        assert!(low > 4.0 && low < BASS_BOOST_DB + 0.1, "{}", low);
+        assert!(high.abs() < 0.2, "{}", high);
+    }
+
+    #[test]
+    fn eq_preset_follows_its_curve() {
+        let filters = Filters {
+            eq: EqPreset::Rock,
+            ..Filters::default()
+        };
+
+        for (freq, gain) in EQ_BANDS.into_iter().zip(EqPreset::Rock.gains()) {
+            let measured = tone_gain_db(&filters, freq);
+            assert!((measured - gain).abs() < 1.5, "{} Hz: {}", freq, measured);
+        }
+    }
+
+    #[test]
+    fn flat_eq_adds_no_stages() {
+        assert!(Filters::default().tone_stages(RATE, 2).is_empty());
+    }
+
+    #[test]
+    fn auto_pan_keeps_power_constant() {
+        let mut pan = AutoPan::new(RATE, 2);
+        let mut samples = vec![0.5; RATE as usize * 2 * 10];
+        pan.process(&mut samples);
+
+        for frame in samples.chunks_exact(2) {
+            let power = frame[0].powi(2) + frame[1].powi(2);
+            assert!((power - 0.5).abs() < 1e-4, "{}", power);
+        }
+    }
+
+    #[test]
+    fn no_filters_is_passthrough() {
+        let filters = Filters::default();
+        let mut stages = filters.tone_stages(RATE, 2);
+        stages.extend(filters.output_stages(RATE, 2));
+        assert!(stages.is_empty());
+
+        let input = stereo_sine(440.0, -3.0, 1.0);
+        let mut output = input.clone();
+        run(&mut stages, &mut output);
+
+        assert!(crate::pcm::PcmConfig::default().is_passthrough());
+        assert_eq!(output, input);
+    }
+}
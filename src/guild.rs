@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+
+use crate::cfg::{DEFAULT_VOLUME, GUILDS_FILE, NORMALIZE_TARGET_LUFS};
+use crate::filters::Filters;
+use crate::pcm::PcmConfig;
+use crate::state::GuildStoreKey;
+use crate::store::{load_json, save_json};
+use crate::tts::TtsSettings;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopMode {
+    #[default]
+    Off,
+    Track,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub volume: f32,
+    pub normalize: bool,
+    pub dj_role: Option<u64>,
+    pub loop_mode: LoopMode,
+    pub filters: Filters,
+    pub autoplay: bool,
+    pub tts: TtsSettings,
+    /// Whether talking over the bot cuts its reply short.
+    pub barge_in: bool,
+    pub captions: bool,
+    /// Where captions go, the voice channel's own chat when unset.
+    pub captions_channel: Option<u64>,
+    /// Users who don't want their speech captioned.
+    pub captions_opt_out: HashSet<u64>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            volume: DEFAULT_VOLUME,
+            normalize: false,
+            dj_role: None,
+            loop_mode: LoopMode::Off,
+            filters: Filters::default(),
+            autoplay: false,
+            tts: TtsSettings::default(),
+            barge_in: false,
+            captions: false,
+            captions_channel: None,
+            captions_opt_out: HashSet::new(),
+        }
+    }
+}
+
+impl GuildSettings {
+    pub fn pcm_config(&self) -> PcmConfig {
+        PcmConfig {
+            normalize: self.normalize.then_some(NORMALIZE_TARGET_LUFS),
+            filters: self.filters.clone(),
+        }
+    }
+}
+
+/// Per-guild settings, persisted to `GUILDS_FILE` on every change.
+pub struct GuildStore {
+    settings: DashMap<u64, GuildSettings>,
+}
+
+impl GuildStore {
+    pub fn load() -> Self {
+        let saved: HashMap<u64, GuildSettings> = load_json(GUILDS_FILE);
+
+        Self {
+            settings: saved.into_iter().collect(),
+        }
+    }
+
+    pub fn get(&self, guild_id: u64) -> GuildSettings {
+        self.settings
+            .get(&guild_id)
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn update<F: FnOnce(&mut GuildSettings)>(&self, guild_id: u64, f: F) -> GuildSettings {
+        let settings = {
+            let mut entry = self.settings.entry(guild_id).or_default();
+            f(&mut entry);
+            entry.clone()
+        };
+
+        self.save();
+
+        settings
+    }
+
+    fn save(&self) {
+        let snapshot = self
+            .settings
+            .iter()
+            .map(|e| (*e.key(), e.value().clone()))
+            .collect::<HashMap<_, _>>();
+
+        if let Err(e) = save_json(GUILDS_FILE, &snapshot) {
+            error!("Failed to save guild settings: {:?}", e);
+        }
+    }
+}
+
+pub async fn get_guild_store(ctx: &Context) -> Arc<GuildStore> {
+    let data = ctx.data.read().await;
+    data.get::<GuildStoreKey>()
+        .cloned()
+        .expect("Guild store not found")
+}
@@ -2,15 +2,27 @@ use log::{error, info};
 
 use crate::bot::Bot;
 
+/// Where a message was said.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MessageSource {
+    #[default]
+    Text,
+    Voice,
+}
+
 #[derive(Debug, Clone)]
 pub struct SavedMessage {
     pub author: String,
     pub content: String,
+    pub source: MessageSource,
 }
 
 impl SavedMessage {
     pub fn get(&self) -> String {
-        format!("{}: {}", self.author, self.content)
+        match self.source {
+            MessageSource::Text => format!("{}: {}", self.author, self.content),
+            MessageSource::Voice => format!("{} (voice): {}", self.author, self.content),
+        }
     }
 }
 
@@ -18,10 +30,20 @@ pub type History = Vec<SavedMessage>;
 
 impl Bot {
     pub fn add_history(&self, author_id: &str, msg: &str) {
+        self.add_history_from(author_id, msg, MessageSource::Text);
+    }
+
+    /// Records something said in a voice channel, either a transcript or a spoken reply.
+    pub fn add_voice_history(&self, author_id: &str, msg: &str) {
+        self.add_history_from(author_id, msg, MessageSource::Voice);
+    }
+
+    fn add_history_from(&self, author_id: &str, msg: &str, source: MessageSource) {
         if let Ok(mut history) = self.history.lock() {
             history.push(SavedMessage {
                 author: author_id.to_string(),
                 content: msg.to_string(),
+                source,
             });
         } else {
             error!("Failed to acquire lock for history");
@@ -48,15 +70,20 @@ impl Bot {
         None
     }
 
+    /// The last `n` messages, oldest first, one per line.
     pub fn get_history_text(&self, n: usize) -> String {
-        let mut msg = String::new();
-
         if let Ok(history) = self.history.lock() {
-            for i in 0..(n.min(history.len())) {
-                msg.push_str(&history[i].get());
-            }
+            let lines = history
+                .iter()
+                .rev()
+                .take(n)
+                .rev()
+                .map(|m| m.get())
+                .collect::<Vec<_>>();
+
+            return lines.join("\n");
         }
 
-        msg
+        String::new()
     }
 }
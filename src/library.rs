@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use songbird::input::codecs::PROBE;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+use crate::cfg::MUSIC_DIR;
+
+pub const AUDIO_EXTENSIONS: [&str; 9] = [
+    "mp3", "m4a", "mp4", "aac", "alac", "flac", "wav", "ogg", "opus",
+];
+
+#[derive(Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<u64>,
+}
+
+pub fn is_audio_file(name: &str) -> bool {
+    let name = name.split(['?', '#']).next().unwrap_or(name);
+
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Resolves a path relative to the library, refusing anything outside of it.
+pub fn library_file(name: &str) -> Option<PathBuf> {
+    let root = Path::new(MUSIC_DIR).canonicalize().ok()?;
+    let path = root.join(name).canonicalize().ok()?;
+
+    (path.starts_with(&root) && path.is_file() && is_audio_file(name)).then_some(path)
+}
+
+/// Files in the library whose relative path contains every word of `query`.
+pub fn search_library(query: &str, limit: usize) -> Vec<String> {
+    let words = query
+        .to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut files = Vec::new();
+    collect_files(Path::new(MUSIC_DIR), &mut files);
+
+    let mut matches = files
+        .iter()
+        .filter_map(|path| path.strip_prefix(MUSIC_DIR).ok())
+        .map(|path| path.display().to_string())
+        .filter(|name| {
+            let lower = name.to_lowercase();
+            words.iter().all(|w| lower.contains(w))
+        })
+        .collect::<Vec<_>>();
+
+    matches.sort();
+    matches.truncate(limit);
+    matches
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path.to_str().map(is_audio_file).unwrap_or(false) {
+            files.push(path);
+        }
+    }
+}
+
+/// Reads title/artist tags and duration from a local audio file.
+pub fn read_tags(path: &Path) -> Result<Tags, Error> {
+    let file = fs::File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut probed = PROBE.format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut tags = Tags::default();
+
+    let mut apply = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => tags.title = Some(tag.value.to_string()),
+                Some(StandardTagKey::Artist) => tags.artist = Some(tag.value.to_string()),
+                _ => {}
+            }
+        }
+    };
+
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        apply(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply(revision);
+    }
+
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        if let (Some(frames), Some(rate)) = (params.n_frames, params.sample_rate) {
+            tags.duration = Some(frames / rate as u64);
+        }
+    }
+
+    Ok(tags)
+}
@@ -0,0 +1,255 @@
+use std::env;
+use std::sync::Arc;
+
+use log::info;
+use reqwest::{Client as HttpClient, Url};
+use serenity::all::GuildId;
+use serenity::client::Context;
+use serenity::prelude::Mutex;
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use songbird::input::{Compose, HttpRequest, Input, LiveInput, YoutubeDl};
+use songbird::tracks::TrackHandle;
+use songbird::Call;
+
+use crate::cfg::{MAX_GUILD_TRACKS, MAX_TRACK_SECS, MAX_USER_TRACKS};
+use crate::music::{enqueue, get_http_client};
+use crate::state::BlocklistKey;
+use crate::track::{format_duration, TrackInfo, TrackInfoKey, TrackSource};
+
+/// Why a track wasn't queued.
+pub enum Rejection {
+    TooLong { duration: u64 },
+    UserQueueFull,
+    GuildQueueFull,
+    BlockedDomain(String),
+    BlockedKeyword,
+    UnknownDuration,
+}
+
+impl Rejection {
+    pub fn describe(&self) -> String {
+        match self {
+            Rejection::TooLong { duration } => format!(
+                "That track is {} long, the limit is {}.",
+                format_duration(*duration),
+                format_duration(MAX_TRACK_SECS)
+            ),
+            Rejection::UserQueueFull => {
+                format!("You already have {} tracks queued.", MAX_USER_TRACKS)
+            }
+            Rejection::GuildQueueFull => {
+                format!("The queue is full, {} tracks max.", MAX_GUILD_TRACKS)
+            }
+            Rejection::BlockedDomain(domain) => format!("Links from {} aren't allowed.", domain),
+            Rejection::BlockedKeyword => "That contains a blocked word.".to_string(),
+            Rejection::UnknownDuration => {
+                "Couldn't tell how long that file is, so it can't be queued.".to_string()
+            }
+        }
+    }
+}
+
+/// Hosts and words that can't be queued, read from the comma separated
+/// `BLOCKED_DOMAINS` and `BLOCKED_KEYWORDS` env vars.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    /// Hosts links can't be queued from, subdomains included.
+    domains: Vec<String>,
+    /// Words that stop a track from being queued when found in its title or search.
+    keywords: Vec<String>,
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+impl Blocklist {
+    pub fn from_env() -> Self {
+        let blocklist = Self {
+            domains: env_list("BLOCKED_DOMAINS"),
+            keywords: env_list("BLOCKED_KEYWORDS"),
+        };
+
+        info!(
+            "Blocking {} domains and {} keywords",
+            blocklist.domains.len(),
+            blocklist.keywords.len()
+        );
+
+        blocklist
+    }
+
+    fn blocked_domain(&self, url: &str) -> Option<String> {
+        let host = Url::parse(url).ok()?.host_str()?.to_lowercase();
+
+        self.domains
+            .iter()
+            .find(|domain| host == **domain || host.ends_with(&format!(".{}", domain)))
+            .cloned()
+    }
+
+    fn blocked_keyword(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+
+        self.keywords.iter().any(|keyword| text.contains(keyword))
+    }
+}
+
+async fn get_blocklist(ctx: &Context) -> Arc<Blocklist> {
+    let data = ctx.data.read().await;
+    data.get::<BlocklistKey>()
+        .cloned()
+        .expect("Blocklist not found")
+}
+
+/// Checks what a user typed before anything is looked up.
+pub async fn check_search(ctx: &Context, search: &str) -> Result<(), Rejection> {
+    let blocklist = get_blocklist(ctx).await;
+
+    if let Some(domain) = blocklist.blocked_domain(search) {
+        return Err(Rejection::BlockedDomain(domain));
+    }
+
+    if blocklist.blocked_keyword(search) {
+        return Err(Rejection::BlockedKeyword);
+    }
+
+    Ok(())
+}
+
+/// Checks a resolved track against the blocklists and duration limit,
+/// looking up the duration of links that didn't come with one.
+pub async fn check_content(ctx: &Context, track: &mut TrackInfo) -> Result<(), Rejection> {
+    let blocklist = get_blocklist(ctx).await;
+
+    if let TrackSource::Youtube(url) | TrackSource::Http(url) = &track.source {
+        if let Some(domain) = blocklist.blocked_domain(url) {
+            return Err(Rejection::BlockedDomain(domain));
+        }
+    }
+
+    if blocklist.blocked_keyword(&track.display()) {
+        return Err(Rejection::BlockedKeyword);
+    }
+
+    match (track.duration, &track.source) {
+        (None, TrackSource::Youtube(url)) => {
+            let client = get_http_client(ctx).await;
+            if let Ok(metadata) = YoutubeDl::new(client, url.clone()).aux_metadata().await {
+                track.duration = metadata.duration.map(|d| d.as_secs());
+            }
+        }
+        (None, TrackSource::Http(url)) => {
+            let client = get_http_client(ctx).await;
+            track.duration = probe_duration(client, url.clone()).await;
+
+            // Files are the easiest way around the limit, so unknown lengths don't pass.
+            if track.duration.is_none() {
+                return Err(Rejection::UnknownDuration);
+            }
+        }
+        _ => {}
+    }
+
+    match track.duration {
+        Some(duration) if duration > MAX_TRACK_SECS => Err(Rejection::TooLong { duration }),
+        _ => Ok(()),
+    }
+}
+
+/// Length of a linked audio file from its headers, only fetching as much as the probe reads.
+async fn probe_duration(client: HttpClient, url: String) -> Option<u64> {
+    let input: Input = HttpRequest::new(client, url).into();
+    let input = input
+        .make_playable_async(&CODEC_REGISTRY, &PROBE)
+        .await
+        .ok()?;
+
+    let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+        return None;
+    };
+
+    let params = &parsed.format.default_track()?.codec_params;
+    Some(params.n_frames? / params.sample_rate?.max(1) as u64)
+}
+
+/// Checks there's room in the queue for another of `requester`'s tracks.
+/// Hold the call's lock until the track is queued, so parallel requests can't both get in.
+pub async fn check_queue(handler: &Call, requester: u64) -> Result<(), Rejection> {
+    let handles = handler.queue().current_queue();
+
+    if handles.len() >= MAX_GUILD_TRACKS {
+        return Err(Rejection::GuildQueueFull);
+    }
+
+    let mut queued = 0;
+    for handle in &handles {
+        if let Some(track) = handle.typemap().read().await.get::<TrackInfoKey>() {
+            if track.requester == requester && !track.radio {
+                queued += 1;
+            }
+        }
+    }
+
+    if queued >= MAX_USER_TRACKS {
+        return Err(Rejection::UserQueueFull);
+    }
+
+    Ok(())
+}
+
+/// Runs every check a newly requested track has to pass, then queues it.
+pub async fn enqueue_checked(
+    ctx: &Context,
+    guild_id: GuildId,
+    handler_lock: &Mutex<Call>,
+    mut track: TrackInfo,
+) -> Result<TrackHandle, Rejection> {
+    check_content(ctx, &mut track).await?;
+
+    let mut handler = handler_lock.lock().await;
+    check_queue(&handler, track.requester).await?;
+
+    Ok(enqueue(ctx, guild_id, &mut handler, track).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocklist() -> Blocklist {
+        Blocklist {
+            domains: vec!["example.com".to_string()],
+            keywords: vec!["earrape".to_string()],
+        }
+    }
+
+    #[test]
+    fn blocks_domains_and_subdomains() {
+        let blocklist = blocklist();
+
+        assert_eq!(
+            blocklist.blocked_domain("https://music.example.com/song.mp3"),
+            Some("example.com".to_string())
+        );
+        assert!(blocklist
+            .blocked_domain("https://example.com.evil.org/")
+            .is_none());
+        assert!(blocklist
+            .blocked_domain("https://notexample.com/")
+            .is_none());
+    }
+
+    #[test]
+    fn blocks_keywords_ignoring_case() {
+        let blocklist = blocklist();
+
+        assert!(blocklist.blocked_keyword("Despacito EARRAPE edition"));
+        assert!(!blocklist.blocked_keyword("Despacito"));
+    }
+}
@@ -1,5 +1,3 @@
-use log;
-
 use crate::cfg::LOG_FILE;
 
 pub fn setup_logging() {
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+/// Blocks quieter than this are ignored entirely (BS.1770 absolute gate).
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this far below the ungated mean are ignored (BS.1770 relative gate).
+const RELATIVE_GATE: f64 = -10.0;
+/// Width of the loudness buckets blocks are summed into, which is how
+/// precisely the relative gate is applied.
+const BUCKET_LU: f64 = 0.1;
+/// Anything louder lands in the top bucket.
+const MAX_BLOCK_LUFS: f64 = 10.0;
+
+/// Second order IIR filter in transposed direct form II, with `a[0]` normalized to 1.
+#[derive(Clone, Copy, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [1.0, a[1] / a[0], a[2] / a[0]],
+            z: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// K-weighting pre-filter: a high shelf modelling the head followed by a
+/// high-pass (RLB) filter, with coefficients derived for any sample rate.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Integrated loudness meter following EBU R128 / ITU-R BS.1770: K-weighted
+/// mean square over 400ms blocks with 75% overlap, gated absolutely and relatively.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Samples per channel in a 100ms step.
+    step_len: usize,
+    step_pos: usize,
+    step_sum: f64,
+    /// Energy of the last four steps, together forming one 400ms block.
+    steps: [f64; 4],
+    steps_seen: usize,
+    /// Energy sum and count of the blocks passing the absolute gate, per bucket.
+    buckets: Vec<(f64, usize)>,
+    gated_sum: f64,
+    gated_count: usize,
+}
+
+impl LoudnessMeter {
+    pub fn new(rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+
+        Self {
+            channels,
+            filters: vec![k_weighting(rate); channels],
+            step_len: (rate as usize / 10).max(1),
+            step_pos: 0,
+            step_sum: 0.0,
+            steps: [0.0; 4],
+            steps_seen: 0,
+            buckets: vec![(0.0, 0); ((MAX_BLOCK_LUFS - ABSOLUTE_GATE) / BUCKET_LU) as usize + 1],
+            gated_sum: 0.0,
+            gated_count: 0,
+        }
+    }
+
+    /// Feeds interleaved samples.
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                let y = high_pass.process(shelf.process(*sample as f64));
+                self.step_sum += y * y;
+            }
+
+            self.step_pos += 1;
+            if self.step_pos == self.step_len {
+                self.finish_step();
+            }
+        }
+    }
+
+    fn finish_step(&mut self) {
+        self.steps.rotate_left(1);
+        self.steps[3] = self.step_sum / self.step_len as f64;
+        self.steps_seen += 1;
+        self.step_pos = 0;
+        self.step_sum = 0.0;
+
+        if self.steps_seen >= 4 {
+            self.add_block(self.steps.iter().sum::<f64>() / 4.0);
+        }
+    }
+
+    fn add_block(&mut self, energy: f64) {
+        let loudness = energy_to_lufs(energy);
+        if loudness <= ABSOLUTE_GATE {
+            return;
+        }
+
+        let index = bucket_index(loudness).min(self.buckets.len() - 1);
+        self.buckets[index].0 += energy;
+        self.buckets[index].1 += 1;
+        self.gated_sum += energy;
+        self.gated_count += 1;
+    }
+
+    /// Seconds of audio measured so far.
+    pub fn measured_secs(&self) -> f64 {
+        self.steps_seen as f64 / 10.0
+    }
+
+    /// Gated integrated loudness in LUFS, `None` until something audible was measured.
+    /// Costs the same however long the track is.
+    pub fn integrated(&self) -> Option<f64> {
+        if self.gated_count == 0 {
+            return None;
+        }
+
+        let threshold = energy_to_lufs(self.gated_sum / self.gated_count as f64) + RELATIVE_GATE;
+        let first = bucket_index(threshold).min(self.buckets.len());
+
+        let (sum, count) = self.buckets[first..]
+            .iter()
+            .fold((0.0, 0usize), |(sum, count), (s, c)| (sum + s, count + c));
+
+        (count > 0).then(|| energy_to_lufs(sum / count as f64))
+    }
+}
+
+/// Bucket a loudness above the absolute gate falls into.
+fn bucket_index(loudness: f64) -> usize {
+    ((loudness - ABSOLUTE_GATE) / BUCKET_LU).max(0.0) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn sine(freq: f64, dbfs: f64, secs: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+
+        (0..(RATE as f64 * secs) as usize)
+            .map(|i| (amplitude * (2.0 * PI * freq * i as f64 / RATE as f64).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn sine_at_minus_20_dbfs_is_minus_23_lufs() {
+        let mut meter = LoudnessMeter::new(RATE, 1);
+        meter.add(&sine(1000.0, -20.0, 5.0));
+
+        let loudness = meter.integrated().unwrap();
+        assert!((loudness + 23.0).abs() < 0.5, "{}", loudness);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut meter = LoudnessMeter::new(RATE, 2);
+        meter.add(&vec![0.0; RATE as usize * 2 * 5]);
+
+        assert_eq!(meter.integrated(), None);
+        assert_eq!(meter.measured_secs(), 5.0);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated() {
+        let mut meter = LoudnessMeter::new(RATE, 1);
+        meter.add(&sine(1000.0, -20.0, 5.0));
+        meter.add(&sine(1000.0, -50.0, 5.0));
+
+        let loudness = meter.integrated().unwrap();
+        assert!((loudness + 23.0).abs() < 0.5, "{}", loudness);
+    }
+}
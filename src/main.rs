@@ -1,17 +1,41 @@
 extern crate dotenv;
 
+mod audio_cache;
 mod bot;
+mod captions;
 mod cfg;
+mod dj;
+mod ducking;
+mod filters;
+mod guild;
 mod history;
+mod library;
+mod limits;
 mod logging;
+mod loudness;
 mod message;
 mod music;
 mod openai;
+mod pcm;
+mod playlist;
+mod plays;
+mod radio;
+mod search;
+mod segmenter;
+mod session;
+mod soundboard;
 mod state;
+mod store;
+mod stt;
+mod track;
+mod tts;
 mod voice;
+mod voice_command;
+mod watchdog;
 
 use std::collections::HashSet;
 use std::env;
+use std::sync::Arc;
 
 use chrono::Utc;
 use dotenv::dotenv;
@@ -26,12 +50,29 @@ use serenity::model::gateway::Ready;
 use serenity::prelude::*;
 use songbird::driver::DecodeMode;
 use songbird::SerenityInit;
+use tokio::signal::unix::{signal, SignalKind};
 
+use crate::audio_cache::AudioCache;
 use crate::bot::Bot;
+use crate::captions::CAPTIONS_COMMAND;
 use crate::cfg::BOT_ID;
+use crate::guild::GuildStore;
+use crate::history::MessageSource;
+use crate::limits::Blocklist;
 use crate::logging::setup_logging;
 use crate::music::*;
-use crate::state::{HttpKey, ShardManagerContainer};
+use crate::playlist::{PlaylistStore, PLAYLIST_COMMAND};
+use crate::plays::{HISTORY_COMMAND, REQUEUE_COMMAND, TOP_COMMAND};
+use crate::radio::AUTOPLAY_COMMAND;
+use crate::search::build_search_provider;
+use crate::session::*;
+use crate::soundboard::SB_COMMAND;
+use crate::state::{
+    AudioCacheKey, BlocklistKey, BotKey, DuckingKey, GuildStoreKey, HttpKey, PendingSessionsKey,
+    PlayStoreKey, PlaylistStoreKey, RadioKey, SearchKey, ShardManagerContainer, SkipVotesKey,
+};
+use crate::tts::VOICE_COMMAND;
+use crate::voice::BARGEIN_COMMAND;
 
 #[async_trait]
 impl EventHandler for Bot {
@@ -69,7 +110,11 @@ impl EventHandler for Bot {
         let mentioned = content.contains("adam");
         let dm = msg.is_private();
         let reply = if let Some(last) = self.get_last_2_msgs() {
-            last.0.author == msg.author.name && last.1.author == "adam"
+            // Spoken replies don't count, only a text conversation with the bot.
+            last.0.author == msg.author.name
+                && last.1.author == "adam"
+                && last.0.source == MessageSource::Text
+                && last.1.source == MessageSource::Text
         } else {
             false
         };
@@ -101,9 +146,44 @@ impl EventHandler for Bot {
 }
 
 #[group]
-#[commands(queue, skip, stop, vol)]
+#[commands(
+    queue,
+    skip,
+    stop,
+    clear,
+    vol,
+    normalize,
+    filter,
+    dj,
+    np,
+    library,
+    repeat,
+    resume_session,
+    export_queue,
+    import_queue,
+    playlist,
+    autoplay,
+    history,
+    top,
+    requeue,
+    sb,
+    voice,
+    bargein,
+    captions
+)]
 struct General;
 
+/// Waits for ctrl+c, or SIGTERM as sent by `docker stop`.
+async fn shutdown_signal() {
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Could not register SIGTERM handler");
+
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res.expect("Could not register ctrl+c handler"),
+        _ = terminate.recv() => {}
+    }
+}
+
 #[tokio::main]
 async fn main() {
     if cfg!(debug_assertions) {
@@ -134,13 +214,27 @@ async fn main() {
     framework.configure(Configuration::new().owners(owners).prefix("~"));
 
     let yt_client = reqwest::Client::new();
+    let search_provider = build_search_provider(yt_client.clone());
     let songbird_cfg = songbird::Config::default().decode_mode(DecodeMode::Decode);
 
+    let bot = Bot::new();
+
     let mut client = Client::builder(token, intents)
-        .event_handler(Bot::new())
+        .event_handler(bot.clone())
         .framework(framework)
         .register_songbird_from_config(songbird_cfg)
+        .type_map_insert::<BotKey>(bot)
         .type_map_insert::<HttpKey>(yt_client)
+        .type_map_insert::<SearchKey>(search_provider)
+        .type_map_insert::<BlocklistKey>(Arc::new(Blocklist::from_env()))
+        .type_map_insert::<GuildStoreKey>(Arc::new(GuildStore::load()))
+        .type_map_insert::<DuckingKey>(Arc::default())
+        .type_map_insert::<SkipVotesKey>(Arc::default())
+        .type_map_insert::<PendingSessionsKey>(Arc::default())
+        .type_map_insert::<PlaylistStoreKey>(Arc::new(PlaylistStore::load()))
+        .type_map_insert::<RadioKey>(Arc::default())
+        .type_map_insert::<PlayStoreKey>(Arc::default())
+        .type_map_insert::<AudioCacheKey>(Arc::new(AudioCache::load()))
         .await
         .expect("Error creating client");
 
@@ -150,10 +244,10 @@ async fn main() {
     }
 
     let shard_manager = client.shard_manager.clone();
+    let data = client.data.clone();
     tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Could not register ctrl+c handler");
+        shutdown_signal().await;
+        save_all_sessions(&data).await;
         shard_manager.shutdown_all().await;
     });
 
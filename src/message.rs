@@ -12,20 +12,31 @@ impl Bot {
     pub async fn gen_msg(&self, ctx: &Context, msg: &Message) {
         let typing = msg.channel_id.start_typing(&ctx.http);
 
-        if let Ok(text) = self.gen_with_prompt(&msg, SYS_PROMPT).await {
-            self.send_msg(&ctx, &msg, &text).await;
+        if let Ok(text) = self.gen_with_prompt(msg, SYS_PROMPT).await {
+            self.send_msg(ctx, msg, &text).await;
         }
 
         typing.stop();
     }
 
     pub async fn gen_with_prompt(&self, msg: &Message, sys_prompt: &str) -> Result<String, Error> {
+        self.gen_reply(&msg.author.name, &msg.content, sys_prompt)
+            .await
+    }
+
+    /// Replies to `content` from `author` with the recent conversation as context.
+    pub async fn gen_reply(
+        &self,
+        author: &str,
+        content: &str,
+        sys_prompt: &str,
+    ) -> Result<String, Error> {
         let sys_prompt = format!(
             "{}\nConversation history:\n{}",
             sys_prompt,
             self.get_history_text(10)
         );
-        let new_msg = format!("{}: {}", &msg.author.name, &msg.content);
+        let new_msg = format!("{}: {}", author, content);
 
         let res = self
             .client
@@ -61,11 +72,11 @@ impl Bot {
         info!("{}: {}", "adam", res);
 
         self.add_history(&msg.author.name, &msg.content);
-        self.add_history("adam", &res);
+        self.add_history("adam", res);
     }
 
     pub async fn send_msg(&self, ctx: &Context, msg: &Message, res: &str) {
-        self.handle_msg(&msg, &res).await;
+        self.handle_msg(msg, res).await;
 
         if let Err(e) = msg.channel_id.say(&ctx, res).await {
             error!("Failed to send message: {}", e);
@@ -74,7 +85,7 @@ impl Bot {
 
     #[allow(dead_code)]
     pub async fn send_dm(&self, ctx: &Context, msg: &Message, res: &str) {
-        self.handle_msg(&msg, &res).await;
+        self.handle_msg(msg, res).await;
 
         if let Err(e) = msg
             .author
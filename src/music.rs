@@ -1,15 +1,35 @@
-use std::env;
+use std::sync::Arc;
 
+use anyhow::Error;
 use log::{error, info};
 use reqwest::Client as HttpClient;
-use reqwest::Error;
+use serenity::all::GuildId;
+use serenity::async_trait;
 use serenity::client::Context;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::model::channel::Message;
-use songbird::input::YoutubeDl;
-
-use crate::state::HttpKey;
+use songbird::input::File;
+use songbird::tracks::PlayMode;
+use songbird::tracks::TrackHandle;
+use songbird::{Call, Event, EventContext, EventHandler, TrackEvent};
+use tokio::sync::Mutex;
+
+use crate::audio_cache::get_audio_cache;
+use crate::dj::{is_dj, vote_skip};
+use crate::ducking::music_volume;
+use crate::filters::{EqPreset, Filters};
+use crate::guild::{get_guild_store, LoopMode};
+use crate::library::{is_audio_file, library_file, read_tags, search_library};
+use crate::limits::{check_search, enqueue_checked};
+use crate::pcm::process;
+use crate::plays::{mark_started, record_play};
+use crate::radio::continue_radio;
+use crate::search::search_track;
+use crate::session::{offer_session, save_session};
+use crate::state::{HttpKey, SearchKey};
+use crate::track::{format_duration, TrackInfo, TrackInfoKey, TrackSource};
+use crate::voice::{join_voice, user_voice_channel};
 
 #[command]
 #[only_in(guilds)]
@@ -20,27 +40,107 @@ pub async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
 
     let guild_id = msg.guild_id.unwrap();
 
-    let manager = songbird::get(ctx).await.unwrap().clone();
+    if let Err(rejection) = check_search(ctx, search).await {
+        let _ = msg.channel_id.say(&ctx.http, rejection.describe()).await;
+        return Ok(());
+    }
 
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
+    let Some(handler_lock) = get_or_join(ctx, msg).await else {
+        return Ok(());
+    };
+
+    let attachment = msg.attachments.iter().find(|a| is_audio_file(&a.filename));
+
+    let found = match attachment {
+        Some(attachment) => {
+            let mut track = TrackInfo::new(
+                &attachment.filename,
+                TrackSource::Http(attachment.url.clone()),
+                msg.author.id.get(),
+            );
+            track.duration = attachment.duration_secs.map(|d| d as u64);
+            Ok(Some(track))
+        }
+        None => find_song(ctx, search, msg.author.id.get()).await,
+    };
+
+    let track = match found {
+        Ok(Some(track)) => track,
+        Ok(None) => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, format!("Nothing found for: {}", search))
+                .await;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("Search failed: {:?}", e);
+            let _ = msg.channel_id.say(&ctx.http, "Search failed.").await;
+            return Ok(());
+        }
+    };
+
+    info!("Queueing {}", track.url());
+
+    let title = track.display();
+    if let Err(rejection) = enqueue_checked(ctx, guild_id, &handler_lock, track).await {
+        let _ = msg.channel_id.say(&ctx.http, rejection.describe()).await;
+        return Ok(());
+    }
+    let position = handler_lock.lock().await.queue().len();
 
-        let (youtube_dl, url) = find_song(&ctx, search).await?;
+    save_session(&ctx.data, guild_id).await;
 
-        info!("Queueing {}", url);
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            format!("Added {} to queue: position {}", title, position),
+        )
+        .await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn skip(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    info!("Music: skip");
+
+    let guild_id = msg.guild_id.unwrap();
+
+    let outcome = vote_skip(ctx, guild_id, msg.author.id).await;
+
+    let _ = msg.channel_id.say(&ctx.http, outcome.describe()).await;
+
+    Ok(())
+}
 
-        // Use lazy restartable sources to make sure that we don't pay
-        // for decoding, playback on tracks which aren't actually live yet.
-        let handle = handler.enqueue_input(youtube_dl.into()).await;
-        let _ = handle.set_volume(0.05);
+#[command]
+#[only_in(guilds)]
+pub async fn stop(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
 
+    if !is_dj(ctx, guild_id, msg.author.id).await {
         let _ = msg
             .channel_id
-            .say(
-                &ctx.http,
-                format!("Added song to queue: position {}", handler.queue().len()),
-            )
+            .say(&ctx.http, "Only DJs can stop the music.")
             .await;
+        return Ok(());
+    }
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        info!("Stopping");
+
+        let mut handler = handler_lock.lock().await;
+        handler.stop();
+
+        let queue = handler.queue();
+        queue.stop();
+
+        let _ = msg.channel_id.say(&ctx.http, "Queue cleared.").await;
     } else {
         error!("Not in a voice channel");
     }
@@ -50,60 +150,367 @@ pub async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
 
 #[command]
 #[only_in(guilds)]
-pub async fn skip(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    info!("Music: skip");
-
+pub async fn clear(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
     let guild_id = msg.guild_id.unwrap();
 
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can clear the queue.")
+            .await;
+        return Ok(());
+    }
+
     let manager = songbird::get(ctx).await.unwrap().clone();
 
     if let Some(handler_lock) = manager.get(guild_id) {
         let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.skip();
+        let removed = clear_upcoming(&handler);
 
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, format!("Removed {} upcoming tracks.", removed))
+            .await;
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+pub async fn dj(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    let role = if args.message().trim() == "off" {
+        None
+    } else if let Some(role) = msg.mention_roles.first() {
+        Some(role.get())
+    } else {
+        let role = store.get(guild_id.get()).dj_role;
+        let text = match role {
+            Some(role) => format!("DJ role: <@&{}>", role),
+            None => "No DJ role set, everyone can control music.".to_string(),
+        };
+        let _ = msg.channel_id.say(&ctx.http, text).await;
+        return Ok(());
+    };
+
+    store.update(guild_id.get(), |s| s.dj_role = role);
+
+    let text = match role {
+        Some(role) => format!("DJ role set to <@&{}>", role),
+        None => "DJ role removed, everyone can control music.".to_string(),
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn vol(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    if !args.is_empty() && !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can change the volume.")
+            .await;
+        return Ok(());
+    }
+
+    if args.is_empty() {
+        let volume = store.get(guild_id.get()).volume;
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, format!("Volume: {:.0}%", volume * 100.0))
+            .await;
+        return Ok(());
+    }
+
+    let percent = match args.message().trim_end_matches('%').parse::<f32>() {
+        Ok(p) if (0.0..=200.0).contains(&p) => p,
+        _ => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, "Volume must be a number between 0 and 200.")
+                .await;
+            return Ok(());
+        }
+    };
+
+    set_volume(ctx, guild_id, percent / 100.0).await;
+
+    let _ = msg
+        .channel_id
+        .say(&ctx.http, format!("Volume set to {:.0}%", percent))
+        .await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn normalize(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    let enabled = match args.message().trim() {
+        "on" => true,
+        "off" => false,
+        "" => !store.get(guild_id.get()).normalize,
+        _ => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, "Usage: ~normalize [on|off]")
+                .await;
+            return Ok(());
+        }
+    };
+
+    store.update(guild_id.get(), |s| s.normalize = enabled);
+
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Loudness normalization {} for newly queued tracks.",
+                if enabled { "enabled" } else { "disabled" }
+            ),
+        )
+        .await;
+
+    Ok(())
+}
+
+/// Turns audio effects on and off, e.g. `~filter bass`, `~filter eq rock`, `~filter off`.
+#[command]
+#[only_in(guilds)]
+#[aliases("filters")]
+pub async fn filter(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+    let mut filters = store.get(guild_id.get()).filters;
+
+    if args.is_empty() {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, format!("Filters: {}", filters.describe()))
+            .await;
+        return Ok(());
+    }
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can change filters.")
+            .await;
+        return Ok(());
+    }
+
+    let name = args.single::<String>()?.to_lowercase();
+    let value = args.single::<String>().ok().map(|v| v.to_lowercase());
+
+    let valid = match (name.as_str(), value.as_deref()) {
+        ("off" | "clear", None) => {
+            filters = Filters::default();
+            true
+        }
+        ("eq", Some(preset)) => match EqPreset::from_name(preset) {
+            Some(preset) => {
+                filters.eq = preset;
+                true
+            }
+            None => false,
+        },
+        (name, value) => match (filters.flag_mut(name), value) {
+            (Some(flag), None) => {
+                *flag = !*flag;
+                true
+            }
+            (Some(flag), Some("on")) => {
+                *flag = true;
+                true
+            }
+            (Some(flag), Some("off")) => {
+                *flag = false;
+                true
+            }
+            _ => false,
+        },
+    };
+
+    if !valid {
+        let presets = EqPreset::ALL.map(|p| p.name()).join("|");
         let _ = msg
             .channel_id
             .say(
                 &ctx.http,
-                format!("Song skipped: {} in queue.", queue.len()),
+                format!(
+                    "Usage: ~filter [bass|nightcore|8d|limiter] [on|off], ~filter eq <{}>, ~filter off",
+                    presets
+                ),
             )
             .await;
+        return Ok(());
     }
 
+    let filters = store
+        .update(guild_id.get(), |s| s.filters = filters)
+        .filters;
+
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            format!("Filters for newly queued tracks: {}", filters.describe()),
+        )
+        .await;
+
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
-pub async fn stop(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+#[aliases("loop")]
+pub async fn repeat(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    let loop_mode = match args.message().trim() {
+        "off" => LoopMode::Off,
+        "track" | "on" => LoopMode::Track,
+        "" if store.get(guild_id.get()).loop_mode == LoopMode::Off => LoopMode::Track,
+        "" => LoopMode::Off,
+        _ => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, "Usage: ~loop [off|track]")
+                .await;
+            return Ok(());
+        }
+    };
+
+    store.update(guild_id.get(), |s| s.loop_mode = loop_mode);
 
     let manager = songbird::get(ctx).await.unwrap().clone();
-
     if let Some(handler_lock) = manager.get(guild_id) {
-        info!("Stopping");
+        if let Some(current) = handler_lock.lock().await.queue().current() {
+            let _ = match loop_mode {
+                LoopMode::Track => current.enable_loop(),
+                LoopMode::Off => current.disable_loop(),
+            };
+        }
+    }
 
-        let mut handler = handler_lock.lock().await;
-        handler.stop();
+    let text = match loop_mode {
+        LoopMode::Track => "Looping the current track.",
+        LoopMode::Off => "Looping disabled.",
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
 
-        let queue = handler.queue();
-        queue.stop();
+    Ok(())
+}
 
-        let _ = msg.channel_id.say(&ctx.http, "Queue cleared.").await;
-    } else {
-        error!("Not in a voice channel");
-    }
+#[command]
+#[only_in(guilds)]
+pub async fn np(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    let current = match manager.get(guild_id) {
+        Some(call) => call.lock().await.queue().current(),
+        None => None,
+    };
+
+    let Some(handle) = current else {
+        let _ = msg.channel_id.say(&ctx.http, "Nothing playing.").await;
+        return Ok(());
+    };
+
+    let track = handle.typemap().read().await.get::<TrackInfoKey>().cloned();
+    let position = handle
+        .get_info()
+        .await
+        .map(|state| state.position.as_secs())
+        .unwrap_or(0);
+
+    let text = match track {
+        Some(track) => {
+            let progress = match track.duration {
+                Some(duration) => {
+                    format!(
+                        "{}/{}",
+                        format_duration(position),
+                        format_duration(duration)
+                    )
+                }
+                None => format_duration(position),
+            };
+            let requested = if track.radio {
+                "picked by autoplay".to_string()
+            } else {
+                format!("requested by <@{}>", track.requester)
+            };
+            format!(
+                "Now playing: {} [{}] {}",
+                track.display(),
+                progress,
+                requested
+            )
+        }
+        None => format!("Now playing: unknown [{}]", format_duration(position)),
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
 
     Ok(())
 }
 
 #[command]
-#[only_in(guilds)]
-pub async fn vol(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let volume = args.message().parse::<f32>().unwrap() / 100.0;
+pub async fn library(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let matches = search_library(args.message(), 10);
+
+    let text = if matches.is_empty() {
+        "No matching files in the library.".to_string()
+    } else {
+        format!(
+            "Use `~queue <file>` to play:\n{}",
+            matches
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Drops everything queued after the current track, returning how many went.
+pub fn clear_upcoming(handler: &Call) -> usize {
+    handler.queue().modify_queue(|queue| {
+        let upcoming = queue.split_off(queue.len().min(1));
+        for track in &upcoming {
+            let _ = track.stop();
+        }
+        upcoming.len()
+    })
+}
+
+/// Saves the guild volume and applies it to everything queued.
+pub async fn set_volume(ctx: &Context, guild_id: GuildId, volume: f32) {
+    get_guild_store(ctx)
+        .await
+        .update(guild_id.get(), |s| s.volume = volume);
+    let volume = music_volume(ctx, guild_id).await;
 
-    let guild_id = msg.guild_id.unwrap();
     let manager = songbird::get(ctx).await.unwrap().clone();
 
     if let Some(call) = manager.get(guild_id) {
@@ -115,49 +522,156 @@ pub async fn vol(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
             .for_each(|t| {
                 let _ = t.set_volume(volume);
             });
-    } else {
-        error!("Not in a voice channel");
     }
+}
 
-    Ok(())
+/// The guild's voice connection, joining the author's channel if needed.
+pub async fn get_or_join(ctx: &Context, msg: &Message) -> Option<Arc<Mutex<Call>>> {
+    let guild_id = msg.guild_id?;
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        return Some(handler_lock);
+    }
+
+    let Some(channel_id) = user_voice_channel(ctx, guild_id, msg.author.id) else {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Join a voice channel first.")
+            .await;
+        return None;
+    };
+
+    let handler_lock = join_voice(ctx, guild_id, channel_id).await;
+    offer_session(ctx, guild_id, msg.channel_id).await;
+
+    handler_lock
 }
 
-async fn get_http_client(ctx: &Context) -> HttpClient {
+pub async fn get_http_client(ctx: &Context) -> HttpClient {
     let data = ctx.data.read().await;
     data.get::<HttpKey>()
         .cloned()
         .expect("Http client not found")
 }
 
-pub async fn find_song(ctx: &Context, search: &str) -> Result<(YoutubeDl, String), Error> {
+/// Adds a track to the guild queue, tagging the handle with its info.
+pub async fn enqueue(
+    ctx: &Context,
+    guild_id: GuildId,
+    handler: &mut Call,
+    track: TrackInfo,
+) -> TrackHandle {
     let client = get_http_client(ctx).await;
+    let settings = get_guild_store(ctx).await.get(guild_id.get());
+
+    // Use lazy restartable sources to make sure that we don't pay
+    // for decoding, playback on tracks which aren't actually live yet.
+    let input = match get_audio_cache(ctx).await.lookup(&track) {
+        Some(path) => File::new(path).into(),
+        None => track.input(client),
+    };
+    let input = process(input, settings.pcm_config());
+    let handle = handler.enqueue_input(input).await;
+    let _ = handle.set_volume(music_volume(ctx, guild_id).await);
+
+    handle.typemap().write().await.insert::<TrackInfoKey>(track);
+
+    let events = TrackEvents {
+        ctx: ctx.clone(),
+        guild_id,
+    };
+    let _ = handle.add_event(Event::Track(TrackEvent::Play), events.clone());
+    let _ = handle.add_event(Event::Track(TrackEvent::End), events);
+
+    handle
+}
+
+/// Applies guild settings as queued tracks start, and records plays and
+/// keeps the saved session up to date as they finish.
+#[derive(Clone)]
+struct TrackEvents {
+    ctx: Context,
+    guild_id: GuildId,
+}
 
-    if search.starts_with("https://") {
-        let youtube_dl = YoutubeDl::new(client, search.to_string());
-        return Ok((youtube_dl, search.to_string()));
+#[async_trait]
+impl EventHandler for TrackEvents {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+
+        for (state, handle) in tracks.iter() {
+            match state.playing {
+                PlayMode::Play => {
+                    let settings = get_guild_store(&self.ctx).await.get(self.guild_id.get());
+                    if settings.loop_mode == LoopMode::Track {
+                        let _ = handle.enable_loop();
+                    }
+
+                    mark_started(handle).await;
+
+                    let track = handle.typemap().read().await.get::<TrackInfoKey>().cloned();
+                    if let Some(track) = track {
+                        let cache = get_audio_cache(&self.ctx).await;
+                        tokio::spawn(async move { cache.store(&track).await });
+                    }
+
+                    let (ctx, guild_id) = (self.ctx.clone(), self.guild_id);
+                    tokio::spawn(async move { continue_radio(&ctx, guild_id).await });
+                }
+                PlayMode::End | PlayMode::Stop | PlayMode::Errored(_) => {
+                    record_play(&self.ctx, self.guild_id, handle, state).await;
+
+                    let (ctx, guild_id) = (self.ctx.clone(), self.guild_id);
+                    tokio::spawn(async move { save_session(&ctx.data, guild_id).await });
+                }
+                _ => {}
+            }
+        }
+
+        None
     }
+}
 
-    let yt_api_key = env::var("YOUTUBE_API_KEY").expect("YOUTUBE_API_KEY not set");
+/// Resolves a URL, library file or search query to a track, `None` if the
+/// search came back empty.
+pub async fn find_song(
+    ctx: &Context,
+    search: &str,
+    requester: u64,
+) -> Result<Option<TrackInfo>, Error> {
+    if search.starts_with("https://") || search.starts_with("http://") {
+        let source = if is_audio_file(search) {
+            TrackSource::Http(search.to_string())
+        } else {
+            TrackSource::Youtube(search.to_string())
+        };
+        return Ok(Some(TrackInfo::new(search, source, requester)));
+    }
 
-    let search_results = client
-        .get("https://www.googleapis.com/youtube/v3/search")
-        .query(&[
-            ("key", yt_api_key.as_str()),
-            ("type", "video"),
-            ("maxResults", "1"),
-            ("q", search),
-        ])
-        .send()
-        .await?;
-    let search_results = search_results.json::<serde_json::Value>().await?;
+    if let Some(path) = library_file(search) {
+        let mut track = TrackInfo::new(search, TrackSource::File(path.clone()), requester);
 
-    let video_id = search_results["items"][0]["id"]["videoId"]
-        .as_str()
-        .expect("No video found")
-        .to_string();
+        match tokio::task::spawn_blocking(move || read_tags(&path)).await? {
+            Ok(tags) => {
+                track.title = tags.title.unwrap_or(track.title);
+                track.artist = tags.artist;
+                track.duration = tags.duration;
+            }
+            Err(e) => error!("Failed to read tags for {}: {:?}", search, e),
+        }
+
+        return Ok(Some(track));
+    }
 
-    let url = format!("https://www.youtube.com/watch?v={}", video_id);
-    let youtube_dl = YoutubeDl::new(client, url.clone());
+    let provider = {
+        let data = ctx.data.read().await;
+        data.get::<SearchKey>()
+            .cloned()
+            .expect("Search provider not found")
+    };
 
-    Ok((youtube_dl, url))
+    search_track(provider.as_ref(), search, requester).await
 }
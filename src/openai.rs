@@ -30,6 +30,8 @@ pub struct SpeechRequest {
     pub model: String,
     pub input: String,
     pub voice: String,
+    pub speed: f32,
+    pub response_format: String,
 }
 
 pub fn build_json_client(api_key: &str) -> Result<Client, Error> {
@@ -0,0 +1,506 @@
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+use anyhow::Error;
+use serenity::async_trait;
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use songbird::input::{AudioStream, AudioStreamError, AuxMetadata, Compose, Input, RawAdapter};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::cfg::{NORMALIZE_ANALYSIS_SECS, NORMALIZE_MAX_GAIN, NORMALIZE_MIN_GAIN};
+use crate::filters::Filters;
+use crate::loudness::LoudnessMeter;
+
+/// A processing step run over interleaved `f32` samples.
+pub trait Stage: Send + Sync {
+    /// Called once with audio decoded ahead of playback, before any `process` call.
+    fn prime(&mut self, _samples: &[f32]) {}
+
+    fn process(&mut self, samples: &mut [f32]);
+}
+
+/// Which stages a processed track runs through.
+#[derive(Clone, Debug, Default)]
+pub struct PcmConfig {
+    /// Target integrated loudness in LUFS.
+    pub normalize: Option<f64>,
+    pub filters: Filters,
+}
+
+impl PcmConfig {
+    pub fn is_passthrough(&self) -> bool {
+        self.normalize.is_none() && self.filters.is_empty()
+    }
+
+    fn stages(&self, rate: u32, channels: usize) -> Vec<Box<dyn Stage>> {
+        let mut stages = self.filters.tone_stages(rate, channels);
+
+        if let Some(target) = self.normalize {
+            stages.push(Box::new(Normalizer::new(rate, channels, target)));
+        }
+
+        stages.extend(self.filters.output_stages(rate, channels));
+
+        stages
+    }
+}
+
+/// Wraps a lazy input so that it's decoded and run through `config`'s stages
+/// before reaching songbird, inputs which aren't lazy are returned untouched.
+pub fn process(input: Input, config: PcmConfig) -> Input {
+    match input {
+        Input::Lazy(inner) if !config.is_passthrough() => {
+            Input::Lazy(Box::new(Processed { inner, config }))
+        }
+        input => input,
+    }
+}
+
+struct Processed {
+    inner: Box<dyn Compose>,
+    config: PcmConfig,
+}
+
+#[async_trait]
+impl Compose for Processed {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let stream = if self.inner.should_create_async() {
+            self.inner.create_async().await?
+        } else {
+            self.inner.create()?
+        };
+
+        let config = self.config.clone();
+
+        let source = tokio::task::spawn_blocking(move || PcmSource::new(stream, &config))
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?
+            .map_err(|e| AudioStreamError::Fail(e.into()))?;
+
+        // Claiming a higher rate than the audio was decoded at has songbird
+        // play it faster, raising the pitch with it.
+        let rate = (source.rate as f64 * self.config.filters.speed()).round() as u32;
+        let channels = source.channels as u32;
+
+        Ok(AudioStream {
+            input: Box::new(RawAdapter::new(source, rate, channels)),
+            hint: None,
+        })
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        self.inner.aux_metadata().await
+    }
+}
+
+/// Decodes encoded audio to make sure it plays, returning its length in seconds.
+/// Packets that fail to decode are skipped.
+/// Decoding stops once it's past `max_secs`.
+pub fn decoded_duration(bytes: Vec<u8>, extension: &str, max_secs: f64) -> Result<f64, Error> {
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    let mut format = PROBE
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| Error::msg("No audio track"))?;
+    let track_id = track.id;
+    let mut decoder = CODEC_REGISTRY.make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut secs = 0.0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // A corrupt packet only costs a few milliseconds, it shouldn't sink the whole clip.
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        secs += decoded.frames() as f64 / decoded.spec().rate.max(1) as f64;
+
+        if secs > max_secs {
+            break;
+        }
+    }
+
+    if secs == 0.0 {
+        return Err(Error::msg("No audio decoded"));
+    }
+
+    Ok(secs)
+}
+
+/// Decodes a stream to raw interleaved `f32` PCM, as expected by [`RawAdapter`].
+struct PcmSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    rate: u32,
+    channels: usize,
+    stages: Vec<Box<dyn Stage>>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl PcmSource {
+    fn new(stream: AudioStream<Box<dyn MediaSource>>, config: &PcmConfig) -> Result<Self, Error> {
+        let hint = stream.hint.unwrap_or_default();
+        let stream = MediaSourceStream::new(stream.input, Default::default());
+
+        let probed = PROBE.format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| Error::msg("No audio track"))?;
+        let track_id = track.id;
+        let decoder = CODEC_REGISTRY.make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut source = Self {
+            format,
+            decoder,
+            track_id,
+            rate: 0,
+            channels: 0,
+            stages: Vec::new(),
+            pending: Vec::new(),
+            pending_pos: 0,
+        };
+
+        // Decode some audio up front so stages like normalization can start
+        // from a sensible estimate instead of adjusting audibly mid-track.
+        let preroll_secs = if config.normalize.is_some() {
+            NORMALIZE_ANALYSIS_SECS
+        } else {
+            0.0
+        };
+
+        let mut preroll = Vec::new();
+        while let Some(samples) = source.decode_next()? {
+            preroll.extend_from_slice(&samples);
+
+            let secs = preroll.len() as f64 / (source.rate as f64 * source.channels as f64);
+            if secs >= preroll_secs {
+                break;
+            }
+        }
+
+        if source.channels == 0 {
+            return Err(Error::msg("Stream contained no audio"));
+        }
+
+        source.stages = config.stages(source.rate, source.channels);
+        for stage in source.stages.iter_mut() {
+            stage.prime(&preroll);
+        }
+
+        source.push(preroll);
+
+        Ok(source)
+    }
+
+    /// Decodes the next packet of the selected track, `None` at the end of the stream.
+    fn decode_next(&mut self) -> Result<Option<Vec<f32>>, SymphoniaError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(SymphoniaError::ResetRequired) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let spec = *decoded.spec();
+            self.rate = spec.rate;
+            self.channels = spec.channels.count();
+
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+
+            return Ok(Some(buffer.samples().to_vec()));
+        }
+    }
+
+    fn push(&mut self, mut samples: Vec<f32>) {
+        for stage in self.stages.iter_mut() {
+            stage.process(&mut samples);
+        }
+
+        self.pending.drain(..self.pending_pos);
+        self.pending_pos = 0;
+        self.pending
+            .extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+    }
+}
+
+impl Read for PcmSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending_pos >= self.pending.len() {
+            match self.decode_next() {
+                Ok(Some(samples)) => self.push(samples),
+                Ok(None) => return Ok(0),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+
+        let available = &self.pending[self.pending_pos..];
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pending_pos += n;
+
+        Ok(n)
+    }
+}
+
+impl Seek for PcmSource {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+impl MediaSource for PcmSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Applies a gain moving the track's integrated loudness toward `target`,
+/// re-estimated as the track plays and smoothed to avoid audible jumps.
+pub struct Normalizer {
+    meter: LoudnessMeter,
+    channels: usize,
+    target: f64,
+    gain: f32,
+    target_gain: f32,
+    smoothing: f32,
+    /// Samples already measured while priming, not to be counted twice.
+    measured_ahead: usize,
+}
+
+impl Normalizer {
+    pub fn new(rate: u32, channels: usize, target: f64) -> Self {
+        Self {
+            meter: LoudnessMeter::new(rate, channels),
+            channels: channels.max(1),
+            target,
+            gain: 1.0,
+            target_gain: 1.0,
+            // Roughly a one second time constant.
+            smoothing: 1.0 - (-1.0 / rate.max(1) as f32).exp(),
+            measured_ahead: 0,
+        }
+    }
+
+    fn update_target(&mut self) {
+        if let Some(loudness) = self.meter.integrated() {
+            let gain = 10f64.powf((self.target - loudness) / 20.0) as f32;
+            self.target_gain = gain.clamp(NORMALIZE_MIN_GAIN, NORMALIZE_MAX_GAIN);
+        }
+    }
+}
+
+impl Stage for Normalizer {
+    fn prime(&mut self, samples: &[f32]) {
+        self.meter.add(samples);
+        self.update_target();
+        self.gain = self.target_gain;
+        self.measured_ahead += samples.len();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let skip = self.measured_ahead.min(samples.len());
+        self.measured_ahead -= skip;
+
+        let before = self.meter.measured_secs();
+        self.meter.add(&samples[skip..]);
+        if self.meter.measured_secs() > before {
+            self.update_target();
+        }
+
+        for frame in samples.chunks_exact_mut(self.channels) {
+            self.gain += (self.target_gain - self.gain) * self.smoothing;
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::cfg::NIGHTCORE_SPEED;
+
+    const RATE: u32 = 48000;
+
+    fn sine(dbfs: f32, secs: usize) -> Vec<f32> {
+        let amplitude = 10f32.powf(dbfs / 20.0);
+
+        (0..RATE as usize * secs)
+            .map(|i| amplitude * (2.0 * PI * 1000.0 * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    /// Gain the normalizer settles on for a 1 kHz sine played for ten seconds.
+    fn settled_gain(dbfs: f32, target: f64) -> f32 {
+        let mut normalizer = Normalizer::new(RATE, 1, target);
+
+        for mut chunk in sine(dbfs, 10).chunks(960).map(|c| c.to_vec()) {
+            normalizer.process(&mut chunk);
+        }
+
+        assert!((normalizer.gain - normalizer.target_gain).abs() < 0.01);
+        normalizer.target_gain
+    }
+
+    #[test]
+    fn normalizer_converges_to_target() {
+        // -20 dBFS is -23 LUFS, 9 dB short of the target.
+        let gain = settled_gain(-20.0, -14.0);
+        let expected = 10f32.powf(9.0 / 20.0);
+
+        assert!((gain - expected).abs() < 0.1, "{}", gain);
+    }
+
+    #[test]
+    fn normalizer_gain_is_clamped() {
+        assert_eq!(settled_gain(-60.0, -14.0), NORMALIZE_MAX_GAIN);
+        assert_eq!(settled_gain(0.0, -40.0), NORMALIZE_MIN_GAIN);
+    }
+
+    /// A WAV file held in memory, standing in for a downloaded track.
+    struct WavFile(Vec<u8>);
+
+    #[async_trait]
+    impl Compose for WavFile {
+        fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+            let mut hint = Hint::new();
+            hint.with_extension("wav");
+
+            Ok(AudioStream {
+                input: Box::new(Cursor::new(self.0.clone())),
+                hint: Some(hint),
+            })
+        }
+
+        async fn create_async(
+            &mut self,
+        ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+            self.create()
+        }
+
+        fn should_create_async(&self) -> bool {
+            false
+        }
+    }
+
+    fn stereo_wav(samples: &[f32]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for sample in samples {
+            let sample = (sample * i16::MAX as f32) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        bytes.into_inner()
+    }
+
+    #[tokio::test]
+    async fn nightcore_speeds_up_by_configured_factor() {
+        let config = PcmConfig {
+            normalize: None,
+            filters: Filters {
+                nightcore: true,
+                ..Filters::default()
+            },
+        };
+        let mut processed = Processed {
+            inner: Box::new(WavFile(stereo_wav(&sine(-20.0, 2)))),
+            config,
+        };
+
+        let mut stream = processed.create_async().await.unwrap();
+        let mut output = Vec::new();
+        stream.input.read_to_end(&mut output).unwrap();
+
+        // RawAdapter's header: a magic string, then the rate and channel count.
+        let rate = u32::from_le_bytes(output[8..12].try_into().unwrap());
+        let channels = u32::from_le_bytes(output[12..16].try_into().unwrap());
+        let frames = (output.len() - 16) / 4 / channels as usize;
+
+        assert_eq!(rate, (RATE as f64 * NIGHTCORE_SPEED).round() as u32);
+        assert_eq!(frames, RATE as usize * 2);
+
+        let secs = frames as f64 / rate as f64;
+        assert!((secs - 2.0 / NIGHTCORE_SPEED).abs() < 0.001, "{}", secs);
+    }
+}
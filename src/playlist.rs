@@ -0,0 +1,463 @@
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+
+use crate::cfg::PLAYLISTS_FILE;
+use crate::dj::is_dj;
+use crate::limits::{enqueue_checked, Rejection};
+use crate::music::get_or_join;
+use crate::session::save_session;
+use crate::state::PlaylistStoreKey;
+use crate::store::{load_json, save_json};
+use crate::track::{TrackInfo, TrackInfoKey};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Owner {
+    User(u64),
+    Guild(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Playlist {
+    pub name: String,
+    pub owner: Owner,
+    pub tracks: Vec<TrackInfo>,
+}
+
+/// Saved playlists, persisted to `PLAYLISTS_FILE` on every change.
+pub struct PlaylistStore {
+    playlists: Mutex<Vec<Playlist>>,
+}
+
+impl PlaylistStore {
+    pub fn load() -> Self {
+        Self {
+            playlists: Mutex::new(load_json(PLAYLISTS_FILE)),
+        }
+    }
+
+    /// Playlists visible to a user in a guild: their own, then the guild's.
+    pub fn visible(&self, user_id: u64, guild_id: u64) -> Vec<Playlist> {
+        let Ok(playlists) = self.playlists.lock() else {
+            return Vec::new();
+        };
+
+        let mut visible = playlists
+            .iter()
+            .filter(|p| p.owner == Owner::User(user_id) || p.owner == Owner::Guild(guild_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        visible.sort_by_key(|p| (matches!(p.owner, Owner::Guild(_)), p.name.clone()));
+        visible
+    }
+
+    pub fn find(&self, user_id: u64, guild_id: u64, name: &str) -> Option<Playlist> {
+        self.visible(user_id, guild_id)
+            .into_iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Applies `f` to a playlist, creating it first if `create` is set.
+    pub fn update<T, F>(&self, owner: Owner, name: &str, create: bool, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Playlist) -> T,
+    {
+        let result = {
+            let mut playlists = self.playlists.lock().ok()?;
+
+            let index = match playlists
+                .iter()
+                .position(|p| p.owner == owner && p.name.eq_ignore_ascii_case(name))
+            {
+                Some(index) => index,
+                None if create => {
+                    playlists.push(Playlist {
+                        name: name.to_string(),
+                        owner,
+                        tracks: Vec::new(),
+                    });
+                    playlists.len() - 1
+                }
+                None => return None,
+            };
+
+            f(&mut playlists[index])
+        };
+
+        self.save();
+
+        Some(result)
+    }
+
+    /// Creates an empty playlist, `false` if one with that name already exists.
+    pub fn create(&self, owner: Owner, name: &str) -> bool {
+        let created = match self.playlists.lock() {
+            Ok(mut playlists) => {
+                let exists = playlists
+                    .iter()
+                    .any(|p| p.owner == owner && p.name.eq_ignore_ascii_case(name));
+
+                if !exists {
+                    playlists.push(Playlist {
+                        name: name.to_string(),
+                        owner,
+                        tracks: Vec::new(),
+                    });
+                }
+                !exists
+            }
+            Err(_) => false,
+        };
+
+        if created {
+            self.save();
+        }
+
+        created
+    }
+
+    pub fn delete(&self, owner: Owner, name: &str) -> bool {
+        let deleted = match self.playlists.lock() {
+            Ok(mut playlists) => {
+                let before = playlists.len();
+                playlists.retain(|p| !(p.owner == owner && p.name.eq_ignore_ascii_case(name)));
+                playlists.len() != before
+            }
+            Err(_) => false,
+        };
+
+        if deleted {
+            self.save();
+        }
+
+        deleted
+    }
+
+    fn save(&self) {
+        let Ok(playlists) = self.playlists.lock() else {
+            return;
+        };
+
+        if let Err(e) = save_json(PLAYLISTS_FILE, &*playlists) {
+            error!("Failed to save playlists: {:?}", e);
+        }
+    }
+}
+
+async fn get_playlist_store(ctx: &Context) -> Arc<PlaylistStore> {
+    let data = ctx.data.read().await;
+    data.get::<PlaylistStoreKey>()
+        .cloned()
+        .expect("Playlist store not found")
+}
+
+/// Splits `<name> [args...] [--guild]` into the name and remaining args.
+/// Names with spaces need quotes.
+fn split_args(mut args: Args) -> (bool, Option<String>, Vec<String>) {
+    let words = args
+        .quoted()
+        .iter::<String>()
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+
+    let guild = words.iter().any(|w| w == "--guild");
+    let mut rest = words.into_iter().filter(|w| w != "--guild");
+    let name = rest.next();
+
+    (guild, name, rest.collect())
+}
+
+/// Resolves which playlist a modifying command targets, checking guild
+/// playlists are only changed by DJs.
+async fn target(ctx: &Context, msg: &Message, args: Args) -> Option<(Owner, String, Vec<String>)> {
+    let guild_id = msg.guild_id?;
+    let (guild, name, rest) = split_args(args);
+
+    let Some(name) = name else {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Missing playlist name.")
+            .await;
+        return None;
+    };
+
+    if !guild {
+        return Some((Owner::User(msg.author.id.get()), name, rest));
+    }
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can change guild playlists.")
+            .await;
+        return None;
+    }
+
+    Some((Owner::Guild(guild_id.get()), name, rest))
+}
+
+async fn queue_tracks(ctx: &Context, msg: &Message) -> Vec<TrackInfo> {
+    let Some(guild_id) = msg.guild_id else {
+        return Vec::new();
+    };
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let Some(handler_lock) = manager.get(guild_id) else {
+        return Vec::new();
+    };
+
+    let handles = handler_lock.lock().await.queue().current_queue();
+
+    let mut tracks = Vec::new();
+    for handle in handles {
+        if let Some(track) = handle.typemap().read().await.get::<TrackInfoKey>() {
+            // Saved tracks are the user's picks, even if autoplay found them.
+            let mut track = track.clone();
+            track.radio = false;
+            tracks.push(track);
+        }
+    }
+
+    tracks
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(create, add, save, list, show, remove, delete, play)]
+#[description = "Saved playlists: create, add, save, list, show, remove, delete, play"]
+pub async fn playlist(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            "Usage: ~playlist <create|add|save|list|show|remove|delete|play> <name> [--guild]",
+        )
+        .await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn create(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some((owner, name, _)) = target(ctx, msg, args).await else {
+        return Ok(());
+    };
+
+    let store = get_playlist_store(ctx).await;
+    let text = if store.create(owner, &name) {
+        format!("Created playlist {}.", name)
+    } else {
+        format!("Playlist {} already exists.", name)
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Adds the current track.
+#[command]
+#[only_in(guilds)]
+pub async fn add(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some((owner, name, _)) = target(ctx, msg, args).await else {
+        return Ok(());
+    };
+
+    let Some(track) = queue_tracks(ctx, msg).await.into_iter().next() else {
+        let _ = msg.channel_id.say(&ctx.http, "Nothing playing.").await;
+        return Ok(());
+    };
+
+    let title = track.display();
+    let store = get_playlist_store(ctx).await;
+    let added = store.update(owner, &name, false, |p| p.tracks.push(track));
+
+    let text = match added {
+        Some(()) => format!("Added {} to {}.", title, name),
+        None => format!("No playlist named {}.", name),
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Saves the whole queue, creating the playlist if needed.
+#[command]
+#[only_in(guilds)]
+pub async fn save(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some((owner, name, _)) = target(ctx, msg, args).await else {
+        return Ok(());
+    };
+
+    let tracks = queue_tracks(ctx, msg).await;
+    if tracks.is_empty() {
+        let _ = msg.channel_id.say(&ctx.http, "Nothing queued.").await;
+        return Ok(());
+    }
+
+    let count = tracks.len();
+    let store = get_playlist_store(ctx).await;
+    store.update(owner, &name, true, |p| p.tracks.extend(tracks));
+
+    let _ = msg
+        .channel_id
+        .say(&ctx.http, format!("Saved {} tracks to {}.", count, name))
+        .await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn list(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_playlist_store(ctx).await;
+
+    let playlists = store.visible(msg.author.id.get(), guild_id.get());
+
+    let text = if playlists.is_empty() {
+        "No playlists.".to_string()
+    } else {
+        playlists
+            .iter()
+            .map(|p| {
+                let scope = match p.owner {
+                    Owner::User(_) => "yours",
+                    Owner::Guild(_) => "guild",
+                };
+                format!("`{}` ({}, {} tracks)", p.name, scope, p.tracks.len())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn show(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let name = split_args(args).1.unwrap_or_default();
+    let store = get_playlist_store(ctx).await;
+
+    let text = match store.find(msg.author.id.get(), guild_id.get(), &name) {
+        Some(p) if p.tracks.is_empty() => format!("{} is empty.", p.name),
+        Some(p) => p
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("{}. {}", i + 1, t.display()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => format!("No playlist named {}.", name),
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Removes a track by its position in the playlist.
+#[command]
+#[only_in(guilds)]
+pub async fn remove(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some((owner, name, rest)) = target(ctx, msg, args).await else {
+        return Ok(());
+    };
+
+    let Some(index) = rest.first().and_then(|i| i.parse::<usize>().ok()) else {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Usage: ~playlist remove <name> <position>")
+            .await;
+        return Ok(());
+    };
+
+    let store = get_playlist_store(ctx).await;
+    let removed = store.update(owner, &name, false, |p| {
+        (index >= 1 && index <= p.tracks.len()).then(|| p.tracks.remove(index - 1))
+    });
+
+    let text = match removed {
+        Some(Some(track)) => format!("Removed {} from {}.", track.display(), name),
+        Some(None) => format!("{} has no track {}.", name, index),
+        None => format!("No playlist named {}.", name),
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn delete(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some((owner, name, _)) = target(ctx, msg, args).await else {
+        return Ok(());
+    };
+
+    let store = get_playlist_store(ctx).await;
+
+    let text = if store.delete(owner, &name) {
+        format!("Deleted {}.", name)
+    } else {
+        format!("No playlist named {}.", name)
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Enqueues every track in a playlist.
+#[command]
+#[only_in(guilds)]
+pub async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let name = split_args(args).1.unwrap_or_default();
+    let store = get_playlist_store(ctx).await;
+
+    let Some(playlist) = store.find(msg.author.id.get(), guild_id.get(), &name) else {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, format!("No playlist named {}.", name))
+            .await;
+        return Ok(());
+    };
+
+    let Some(handler_lock) = get_or_join(ctx, msg).await else {
+        return Ok(());
+    };
+
+    let mut queued = 0;
+    let mut rejected = None;
+
+    for mut track in playlist.tracks {
+        track.requester = msg.author.id.get();
+        track.radio = false;
+
+        match enqueue_checked(ctx, guild_id, &handler_lock, track).await {
+            Ok(_) => queued += 1,
+            Err(rejection @ (Rejection::UserQueueFull | Rejection::GuildQueueFull)) => {
+                rejected = Some(rejection);
+                break;
+            }
+            Err(rejection) => rejected = Some(rejection),
+        }
+    }
+
+    save_session(&ctx.data, guild_id).await;
+
+    let mut text = format!("Queued {} tracks from {}.", queued, playlist.name);
+    if let Some(rejection) = rejected {
+        text = format!("{} Some were left out: {}", text, rejection.describe());
+    }
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use dashmap::DashMap;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+use songbird::tracks::{PlayMode, TrackHandle, TrackState};
+use songbird::typemap::TypeMapKey;
+
+use crate::cfg::{PLAYS_DIR, PLAY_HISTORY_LIMIT};
+use crate::limits::enqueue_checked;
+use crate::music::get_or_join;
+use crate::session::save_session;
+use crate::state::PlayStoreKey;
+use crate::store::{load_json, save_json};
+use crate::track::{format_duration, TrackInfo, TrackInfoKey};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayOutcome {
+    Finished,
+    Skipped,
+    Errored,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayRecord {
+    pub track: TrackInfo,
+    pub started_at: i64,
+    /// Seconds actually played, counting loops.
+    pub played: u64,
+    pub outcome: PlayOutcome,
+}
+
+/// When a queued track first started playing.
+pub struct StartedAtKey;
+
+impl TypeMapKey for StartedAtKey {
+    type Value = i64;
+}
+
+/// Per-guild play history, each guild stored in its own file under `PLAYS_DIR`
+/// and capped to the last `PLAY_HISTORY_LIMIT` plays.
+#[derive(Default)]
+pub struct PlayStore {
+    guilds: DashMap<u64, Vec<PlayRecord>>,
+}
+
+fn plays_file(guild_id: u64) -> String {
+    format!("{}/{}.json", PLAYS_DIR, guild_id)
+}
+
+impl PlayStore {
+    fn with_guild<T, F: FnOnce(&mut Vec<PlayRecord>) -> T>(&self, guild_id: u64, f: F) -> T {
+        let mut plays = self
+            .guilds
+            .entry(guild_id)
+            .or_insert_with(|| load_json(&plays_file(guild_id)));
+
+        f(&mut plays)
+    }
+
+    pub fn record(&self, guild_id: u64, record: PlayRecord) {
+        self.with_guild(guild_id, |plays| {
+            plays.push(record);
+
+            let excess = plays.len().saturating_sub(PLAY_HISTORY_LIMIT);
+            plays.drain(..excess);
+
+            if let Err(e) = save_json(&plays_file(guild_id), plays) {
+                error!("Failed to save play history for {}: {:?}", guild_id, e);
+            }
+        });
+    }
+
+    /// The last `n` plays, newest first.
+    pub fn recent(&self, guild_id: u64, n: usize) -> Vec<PlayRecord> {
+        self.with_guild(guild_id, |plays| {
+            plays.iter().rev().take(n).cloned().collect()
+        })
+    }
+
+    pub fn since(&self, guild_id: u64, timestamp: i64) -> Vec<PlayRecord> {
+        self.with_guild(guild_id, |plays| {
+            plays
+                .iter()
+                .filter(|p| p.started_at >= timestamp)
+                .cloned()
+                .collect()
+        })
+    }
+}
+
+pub async fn get_play_store(ctx: &Context) -> Arc<PlayStore> {
+    let data = ctx.data.read().await;
+    data.get::<PlayStoreKey>()
+        .cloned()
+        .expect("Play store not found")
+}
+
+/// Notes when a track first starts, resuming from pause doesn't count.
+pub async fn mark_started(handle: &TrackHandle) {
+    let mut typemap = handle.typemap().write().await;
+
+    if !typemap.contains_key::<StartedAtKey>() {
+        typemap.insert::<StartedAtKey>(Utc::now().timestamp());
+    }
+}
+
+/// Adds a finished track to the guild's history, tracks dropped from the
+/// queue before ever playing are left out.
+pub async fn record_play(
+    ctx: &Context,
+    guild_id: GuildId,
+    handle: &TrackHandle,
+    state: &TrackState,
+) {
+    let (track, started_at) = {
+        let typemap = handle.typemap().read().await;
+        match (
+            typemap.get::<TrackInfoKey>().cloned(),
+            typemap.get::<StartedAtKey>().copied(),
+        ) {
+            (Some(track), Some(started_at)) => (track, started_at),
+            _ => return,
+        }
+    };
+
+    let outcome = match state.playing {
+        PlayMode::End => PlayOutcome::Finished,
+        PlayMode::Errored(_) => PlayOutcome::Errored,
+        _ => PlayOutcome::Skipped,
+    };
+
+    get_play_store(ctx).await.record(
+        guild_id.get(),
+        PlayRecord {
+            track,
+            started_at,
+            played: state.play_time.as_secs(),
+            outcome,
+        },
+    );
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn history(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let count = args.single::<usize>().unwrap_or(10).clamp(1, 20);
+
+    let plays = get_play_store(ctx).await.recent(guild_id.get(), count);
+
+    let text = if plays.is_empty() {
+        "Nothing has been played yet.".to_string()
+    } else {
+        let lines = plays
+            .iter()
+            .enumerate()
+            .map(|(i, play)| {
+                let outcome = match play.outcome {
+                    PlayOutcome::Finished => "",
+                    PlayOutcome::Skipped => ", skipped",
+                    PlayOutcome::Errored => ", failed",
+                };
+                format!(
+                    "{}. {} by <@{}> <t:{}:R> ({}{})",
+                    i + 1,
+                    play.track.display(),
+                    play.track.requester,
+                    play.started_at,
+                    format_duration(play.played),
+                    outcome
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("{}\nUse `~requeue <number>` to play one again.", lines)
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Most played tracks or requesters, e.g. `~top requesters month`.
+#[command]
+#[only_in(guilds)]
+pub async fn top(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+
+    let mut requesters = false;
+    let mut period = Some(7 * 24 * 60 * 60);
+
+    for word in args.message().split_whitespace() {
+        match word {
+            "tracks" => requesters = false,
+            "requesters" | "users" => requesters = true,
+            "day" => period = Some(24 * 60 * 60),
+            "week" => period = Some(7 * 24 * 60 * 60),
+            "month" => period = Some(30 * 24 * 60 * 60),
+            "all" => period = None,
+            _ => {
+                let _ = msg
+                    .channel_id
+                    .say(
+                        &ctx.http,
+                        "Usage: ~top [tracks|requesters] [day|week|month|all]",
+                    )
+                    .await;
+                return Ok(());
+            }
+        }
+    }
+
+    let since = period.map(|p| Utc::now().timestamp() - p).unwrap_or(0);
+    let plays = get_play_store(ctx).await.since(guild_id.get(), since);
+
+    let mut counts: HashMap<String, (String, usize)> = HashMap::new();
+    for play in &plays {
+        let (key, label) = if requesters {
+            // Autoplay picks aren't anyone's request.
+            if play.track.radio {
+                continue;
+            }
+            let requester = play.track.requester.to_string();
+            (requester.clone(), format!("<@{}>", requester))
+        } else {
+            (play.track.url(), play.track.display())
+        };
+
+        counts.entry(key).or_insert((label, 0)).1 += 1;
+    }
+
+    let mut ranked = counts.into_values().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let text = if ranked.is_empty() {
+        "Nothing has been played in that period.".to_string()
+    } else {
+        ranked
+            .iter()
+            .take(10)
+            .enumerate()
+            .map(|(i, (label, plays))| format!("{}. {} ({} plays)", i + 1, label, plays))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+/// Queues a track again by its number in `~history`.
+#[command]
+#[only_in(guilds)]
+#[aliases("replay")]
+pub async fn requeue(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let index = args.single::<usize>().unwrap_or(1).max(1);
+
+    let play = get_play_store(ctx)
+        .await
+        .recent(guild_id.get(), index)
+        .into_iter()
+        .nth(index - 1);
+
+    let Some(play) = play else {
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                format!("No play number {} in the history.", index),
+            )
+            .await;
+        return Ok(());
+    };
+
+    let Some(handler_lock) = get_or_join(ctx, msg).await else {
+        return Ok(());
+    };
+
+    let mut track = play.track;
+    track.requester = msg.author.id.get();
+    track.radio = false;
+
+    let title = track.display();
+
+    if let Err(rejection) = enqueue_checked(ctx, guild_id, &handler_lock, track).await {
+        let _ = msg.channel_id.say(&ctx.http, rejection.describe()).await;
+        return Ok(());
+    }
+    let position = handler_lock.lock().await.queue().len();
+
+    save_session(&ctx.data, guild_id).await;
+
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            format!("Added {} to queue: position {}", title, position),
+        )
+        .await;
+
+    Ok(())
+}
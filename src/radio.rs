@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+
+use anyhow::Error;
+use log::{error, info};
+use serenity::all::GuildId;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+use serenity::prelude::Mutex;
+use songbird::Call;
+
+use crate::bot::{get_bot, Bot};
+use crate::cfg::{BOT_ID, RADIO_PROMPT, RADIO_REPEAT_WINDOW, RADIO_SEED_TRACKS, RADIO_SUGGESTIONS};
+use crate::guild::{get_guild_store, LoopMode};
+use crate::limits::check_content;
+use crate::music::{enqueue, find_song};
+use crate::openai::{ChatMessage, ChatRequest, OPENAI_API_URL};
+use crate::plays::get_play_store;
+use crate::session::save_session;
+use crate::state::RadioKey;
+use crate::track::{TrackInfo, TrackInfoKey};
+
+/// Autoplay state for a guild.
+#[derive(Default)]
+pub struct Radio {
+    /// Set while a pick is in flight, so overlapping events don't queue two.
+    filling: bool,
+}
+
+fn already_played(recent: &[TrackInfo], track: &TrackInfo) -> bool {
+    recent
+        .iter()
+        .any(|t| t.url() == track.url() || t.title.eq_ignore_ascii_case(&track.title))
+}
+
+/// The guild's last `RADIO_REPEAT_WINDOW` plays followed by what's queued now,
+/// oldest first. The playing track only reaches the history once it ends.
+async fn recent_tracks(
+    ctx: &Context,
+    guild_id: GuildId,
+    handler_lock: &Mutex<Call>,
+) -> Vec<TrackInfo> {
+    let mut recent = get_play_store(ctx)
+        .await
+        .recent(guild_id.get(), RADIO_REPEAT_WINDOW)
+        .into_iter()
+        .rev()
+        .map(|play| play.track)
+        .collect::<Vec<_>>();
+
+    let handles = handler_lock.lock().await.queue().current_queue();
+    for handle in &handles {
+        if let Some(track) = handle.typemap().read().await.get::<TrackInfoKey>() {
+            recent.push(track.clone());
+        }
+    }
+
+    // A looped or resumed track shows up once per play.
+    recent.dedup_by(|a, b| a.url() == b.url());
+    recent
+}
+
+impl Bot {
+    /// Asks the chat model for songs to follow `seed`, leaving out `avoid`.
+    pub async fn suggest_songs(
+        &self,
+        seed: &[String],
+        avoid: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let prompt = format!(
+            "Just played:\n{}\n\nAlready played, don't suggest these:\n{}\n\nSuggest {} songs.",
+            seed.join("\n"),
+            avoid.join("\n"),
+            RADIO_SUGGESTIONS
+        );
+
+        let data = self
+            .client
+            .post(format!("{OPENAI_API_URL}/chat/completions"))
+            .json(&ChatRequest {
+                model: self.model.clone(),
+                messages: vec![
+                    ChatMessage::new("system", RADIO_PROMPT),
+                    ChatMessage::new("user", &prompt),
+                ],
+            })
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+
+        let text = data["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap_or_default();
+
+        Ok(text
+            .lines()
+            .filter_map(parse_suggestion)
+            .take(RADIO_SUGGESTIONS)
+            .collect())
+    }
+}
+
+/// Strips list markers and quotes the model tends to add around a song.
+fn parse_suggestion(line: &str) -> Option<String> {
+    let mut line = line.trim();
+
+    if let Some((marker, rest)) = line.split_once(['.', ')']) {
+        if !marker.is_empty() && marker.chars().all(|c| c.is_ascii_digit()) {
+            line = rest;
+        }
+    }
+
+    let line = line
+        .trim_start_matches(['-', '*', '•'])
+        .trim()
+        .trim_matches('"')
+        .trim();
+
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Resolves the model's suggestions until one that hasn't played recently is found.
+async fn pick(ctx: &Context, recent: &[TrackInfo]) -> Result<Option<TrackInfo>, Error> {
+    let bot = get_bot(ctx).await;
+
+    let avoid = recent.iter().map(|t| t.display()).collect::<Vec<_>>();
+    let seed = &avoid[avoid.len().saturating_sub(RADIO_SEED_TRACKS)..];
+
+    for suggestion in bot.suggest_songs(seed, &avoid).await? {
+        let Some(mut track) = find_song(ctx, &suggestion, BOT_ID).await? else {
+            continue;
+        };
+
+        if check_content(ctx, &mut track).await.is_err() {
+            continue;
+        }
+
+        if !already_played(recent, &track) {
+            track.radio = true;
+            return Ok(Some(track));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Queues an autoplay pick once the last track in the queue starts playing,
+/// so the next one is ready before the queue runs dry.
+pub async fn continue_radio(ctx: &Context, guild_id: GuildId) {
+    let settings = get_guild_store(ctx).await.get(guild_id.get());
+    if !settings.autoplay || settings.loop_mode == LoopMode::Track {
+        return;
+    }
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let Some(handler_lock) = manager.get(guild_id) else {
+        return;
+    };
+
+    if handler_lock.lock().await.queue().len() > 1 {
+        return;
+    }
+
+    let recent = recent_tracks(ctx, guild_id, &handler_lock).await;
+    if recent.is_empty() {
+        return;
+    }
+
+    {
+        let data = ctx.data.read().await;
+        let radios = data.get::<RadioKey>().expect("Radio state not found");
+        let mut radio = radios.entry(guild_id.get()).or_default();
+
+        if radio.filling {
+            return;
+        }
+        radio.filling = true;
+    }
+
+    let picked = pick(ctx, &recent).await;
+
+    {
+        let data = ctx.data.read().await;
+        let radios = data.get::<RadioKey>().expect("Radio state not found");
+        if let Some(mut radio) = radios.get_mut(&guild_id.get()) {
+            radio.filling = false;
+        };
+    }
+
+    let track = match picked {
+        Ok(Some(track)) => track,
+        Ok(None) => {
+            info!("Autoplay found nothing new to play");
+            return;
+        }
+        Err(e) => {
+            error!("Autoplay failed: {:?}", e);
+            return;
+        }
+    };
+
+    // Autoplay may have been turned off or songs queued while picking.
+    if !get_guild_store(ctx).await.get(guild_id.get()).autoplay {
+        return;
+    }
+
+    {
+        let mut handler = handler_lock.lock().await;
+        if handler.queue().len() > 1 {
+            return;
+        }
+
+        info!("Autoplay queued {}", track.display());
+        enqueue(ctx, guild_id, &mut handler, track).await;
+    }
+
+    save_session(&ctx.data, guild_id).await;
+}
+
+/// Drops autoplay picks that haven't started yet.
+async fn remove_radio_tracks(ctx: &Context, guild_id: GuildId) {
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let Some(handler_lock) = manager.get(guild_id) else {
+        return;
+    };
+
+    let handles = handler_lock.lock().await.queue().current_queue();
+
+    let mut radio = HashSet::new();
+    for handle in handles.iter().skip(1) {
+        if let Some(track) = handle.typemap().read().await.get::<TrackInfoKey>() {
+            if track.radio {
+                radio.insert(handle.uuid());
+            }
+        }
+    }
+
+    if radio.is_empty() {
+        return;
+    }
+
+    handler_lock.lock().await.queue().modify_queue(|queue| {
+        queue.retain(|track| {
+            let keep = !radio.contains(&track.uuid());
+            if !keep {
+                let _ = track.stop();
+            }
+            keep
+        })
+    });
+
+    save_session(&ctx.data, guild_id).await;
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("radio")]
+pub async fn autoplay(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    let enabled = match args.message().trim() {
+        "on" => true,
+        "off" => false,
+        "" => !store.get(guild_id.get()).autoplay,
+        _ => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, "Usage: ~autoplay [on|off]")
+                .await;
+            return Ok(());
+        }
+    };
+
+    store.update(guild_id.get(), |s| s.autoplay = enabled);
+
+    let text = if enabled {
+        "Autoplay enabled, similar songs will be queued once the queue runs out."
+    } else {
+        "Autoplay disabled."
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    if enabled {
+        continue_radio(ctx, guild_id).await;
+    } else {
+        remove_radio_tracks(ctx, guild_id).await;
+    }
+
+    Ok(())
+}
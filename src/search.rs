@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+
+use anyhow::Error;
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use serenity::async_trait;
+use tokio::process::Command;
+
+use crate::track::{TrackInfo, TrackSource};
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    /// Length in seconds, if the provider reports it.
+    pub duration: Option<u64>,
+}
+
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error>;
+}
+
+/// Searches using the YouTube Data API, requires `YOUTUBE_API_KEY`.
+pub struct YoutubeApi {
+    client: HttpClient,
+    api_key: String,
+}
+
+impl YoutubeApi {
+    pub fn new(client: HttpClient, api_key: String) -> Self {
+        Self { client, api_key }
+    }
+
+    /// Looks up video lengths, search results don't include them.
+    async fn durations(&self, videos: &[(String, String)]) -> Result<HashMap<String, u64>, Error> {
+        let ids = videos
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let data = self
+            .client
+            .get("https://www.googleapis.com/youtube/v3/videos")
+            .query(&[
+                ("key", self.api_key.as_str()),
+                ("part", "contentDetails"),
+                ("id", &ids),
+            ])
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+
+        Ok(data["items"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        let id = item["id"].as_str()?;
+                        let duration = item["contentDetails"]["duration"].as_str()?;
+                        Some((id.to_string(), parse_iso_duration(duration)?))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// Parses ISO 8601 durations as used by the Data API, e.g. `PT1H2M3S`.
+fn parse_iso_duration(text: &str) -> Option<u64> {
+    let mut secs = 0;
+    let mut number = String::new();
+
+    for c in text.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            unit => {
+                let value = number.parse::<u64>().ok()?;
+                number.clear();
+                secs += value
+                    * match unit {
+                        'W' => 7 * 24 * 60 * 60,
+                        'D' => 24 * 60 * 60,
+                        'H' => 60 * 60,
+                        'M' => 60,
+                        'S' => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+
+    Some(secs)
+}
+
+#[async_trait]
+impl SearchProvider for YoutubeApi {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+        let data = self
+            .client
+            .get("https://www.googleapis.com/youtube/v3/search")
+            .query(&[
+                ("key", self.api_key.as_str()),
+                ("part", "snippet"),
+                ("type", "video"),
+                ("maxResults", &limit.to_string()),
+                ("q", query),
+            ])
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+
+        if let Some(error) = data["error"]["message"].as_str() {
+            return Err(Error::msg(format!("YouTube API error: {error}")));
+        }
+
+        let videos = data["items"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        let video_id = item["id"]["videoId"].as_str()?;
+                        let title = item["snippet"]["title"].as_str().unwrap_or(video_id);
+                        Some((video_id.to_string(), title.to_string()))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let durations = self.durations(&videos).await.unwrap_or_default();
+
+        let results = videos
+            .into_iter()
+            .map(|(video_id, title)| SearchResult {
+                title,
+                url: format!("https://www.youtube.com/watch?v={video_id}"),
+                duration: durations.get(&video_id).copied(),
+            })
+            .collect();
+
+        Ok(results)
+    }
+}
+
+/// Searches using yt-dlp's `ytsearchN:` pseudo-URLs, no API key needed.
+pub struct YtDlp;
+
+#[async_trait]
+impl SearchProvider for YtDlp {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+        let output = Command::new("yt-dlp")
+            .args(["--flat-playlist", "--no-warnings", "-j"])
+            .arg(format!("ytsearch{limit}:{query}"))
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(Error::msg(format!(
+                "yt-dlp search failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let results = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter_map(|entry| {
+                let video_id = entry["id"].as_str()?;
+                let title = entry["title"].as_str().unwrap_or(video_id);
+
+                Some(SearchResult {
+                    title: title.to_string(),
+                    url: format!("https://www.youtube.com/watch?v={video_id}"),
+                    duration: entry["duration"].as_f64().map(|d| d as u64),
+                })
+            })
+            .collect();
+
+        Ok(results)
+    }
+}
+
+/// The top result for `query` as a track, `None` if the search came back empty.
+pub async fn search_track(
+    provider: &dyn SearchProvider,
+    query: &str,
+    requester: u64,
+) -> Result<Option<TrackInfo>, Error> {
+    let song = provider.search(query, 1).await?.into_iter().next();
+
+    Ok(song.map(|song| {
+        let mut track = TrackInfo::new(&song.title, TrackSource::Youtube(song.url), requester);
+        track.duration = song.duration;
+        track
+    }))
+}
+
+/// Picks a provider from `SEARCH_PROVIDER` (`youtube` or `ytdlp`), defaulting to
+/// the Data API when `YOUTUBE_API_KEY` is set and yt-dlp otherwise.
+pub fn build_search_provider(client: HttpClient) -> Arc<dyn SearchProvider> {
+    let api_key = env::var("YOUTUBE_API_KEY").ok().filter(|k| !k.is_empty());
+    let provider = env::var("SEARCH_PROVIDER").unwrap_or_default();
+
+    match (provider.as_str(), api_key) {
+        ("youtube" | "", Some(api_key)) => {
+            info!("Search provider: YouTube Data API");
+            Arc::new(YoutubeApi::new(client, api_key))
+        }
+        ("youtube", None) => {
+            warn!("SEARCH_PROVIDER=youtube but YOUTUBE_API_KEY not set, using yt-dlp");
+            Arc::new(YtDlp)
+        }
+        _ => {
+            info!("Search provider: yt-dlp");
+            Arc::new(YtDlp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves canned results, for exercising the queue without network access.
+    pub struct MockSearch {
+        results: Vec<SearchResult>,
+    }
+
+    impl MockSearch {
+        pub fn new(results: Vec<SearchResult>) -> Self {
+            Self { results }
+        }
+    }
+
+    #[async_trait]
+    impl SearchProvider for MockSearch {
+        async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+            let query = query.to_lowercase();
+
+            Ok(self
+                .results
+                .iter()
+                .filter(|r| r.title.to_lowercase().contains(&query))
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn provider() -> MockSearch {
+        MockSearch::new(vec![SearchResult {
+            title: "Luis Fonsi - Despacito".to_string(),
+            url: "https://www.youtube.com/watch?v=kJQP7kiw5Fk".to_string(),
+            duration: Some(282),
+        }])
+    }
+
+    #[tokio::test]
+    async fn empty_search_finds_no_track() {
+        let track = search_track(&provider(), "never gonna give you up", 1)
+            .await
+            .unwrap();
+
+        assert!(track.is_none());
+    }
+
+    #[tokio::test]
+    async fn top_result_becomes_track() {
+        let track = search_track(&provider(), "despacito", 1)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(track.title, "Luis Fonsi - Despacito");
+        assert_eq!(track.duration, Some(282));
+        assert_eq!(track.requester, 1);
+        assert!(matches!(track.source, TrackSource::Youtube(url) if url.ends_with("kJQP7kiw5Fk")));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::cfg::{UTTERANCE_MAX_MS, UTTERANCE_MIN_MS, VAD_HANGOVER_MS, VAD_THRESHOLD_DB};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SegmenterConfig {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Level a chunk has to reach to count as speech, in dBFS.
+    pub threshold_db: f32,
+    pub hangover_ms: u32,
+    pub min_ms: u32,
+    pub max_ms: u32,
+}
+
+impl Default for SegmenterConfig {
+    /// Discord's decoded voice: 48kHz stereo.
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            channels: 2,
+            threshold_db: VAD_THRESHOLD_DB,
+            hangover_ms: VAD_HANGOVER_MS,
+            min_ms: UTTERANCE_MIN_MS,
+            max_ms: UTTERANCE_MAX_MS,
+        }
+    }
+}
+
+/// Splits one speaker's audio into utterances: speech starts once a chunk
+/// is loud enough, and ends after `hangover_ms` of quiet or at `max_ms`.
+pub struct Segmenter {
+    config: SegmenterConfig,
+    buffer: Vec<i16>,
+    active: bool,
+    silent_ms: u32,
+    /// Length of the buffer up to the last loud chunk.
+    speech_end: usize,
+}
+
+impl Segmenter {
+    pub fn new(config: SegmenterConfig) -> Self {
+        Self {
+            config,
+            buffer: Vec::new(),
+            active: false,
+            silent_ms: 0,
+            speech_end: 0,
+        }
+    }
+
+    fn samples_to_ms(&self, samples: usize) -> u32 {
+        let frames = samples / self.config.channels.max(1);
+        (frames as u64 * 1000 / self.config.sample_rate.max(1) as u64) as u32
+    }
+
+    fn buffered_ms(&self) -> u32 {
+        self.samples_to_ms(self.buffer.len())
+    }
+
+    /// How long the current utterance has been going, 0 between utterances.
+    pub fn speech_ms(&self) -> u32 {
+        if self.active {
+            self.buffered_ms()
+        } else {
+            0
+        }
+    }
+
+    /// Feeds a chunk of interleaved audio, returning an utterance if this chunk ended one.
+    pub fn push(&mut self, samples: &[i16]) -> Option<Vec<i16>> {
+        if level_db(samples) >= self.config.threshold_db {
+            self.active = true;
+            self.silent_ms = 0;
+            self.buffer.extend_from_slice(samples);
+            self.speech_end = self.buffer.len();
+
+            if self.buffered_ms() >= self.config.max_ms {
+                return self.take();
+            }
+
+            return None;
+        }
+
+        let chunk_ms = self.samples_to_ms(samples.len());
+        self.buffer.extend_from_slice(samples);
+        self.quiet(chunk_ms)
+    }
+
+    /// Notes `ms` of silence from a speaker who sent no audio at all.
+    pub fn push_silence(&mut self, ms: u32) -> Option<Vec<i16>> {
+        self.quiet(ms)
+    }
+
+    fn quiet(&mut self, ms: u32) -> Option<Vec<i16>> {
+        if !self.active {
+            self.buffer.clear();
+            return None;
+        }
+
+        self.silent_ms += ms;
+
+        if self.silent_ms >= self.config.hangover_ms {
+            return self.take();
+        }
+
+        None
+    }
+
+    fn take(&mut self) -> Option<Vec<i16>> {
+        let utterance = std::mem::take(&mut self.buffer);
+        // The quiet tail doesn't count toward the minimum length.
+        let long_enough = self.samples_to_ms(self.speech_end) >= self.config.min_ms;
+
+        self.active = false;
+        self.silent_ms = 0;
+        self.speech_end = 0;
+
+        long_enough.then_some(utterance)
+    }
+}
+
+/// RMS level of a chunk in dBFS, silence being negative infinity.
+pub fn level_db(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+
+    let sum = samples
+        .iter()
+        .map(|&s| {
+            let s = s as f64 / i16::MAX as f64;
+            s * s
+        })
+        .sum::<f64>();
+
+    (10.0 * (sum / samples.len() as f64).log10()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::f64::consts::TAU;
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// 20ms of 48kHz stereo, one voice tick.
+    const TICK: usize = 960 * 2;
+
+    /// Builds a 48kHz stereo WAV alternating tone and silence, each part
+    /// given in milliseconds starting with tone, and reads its samples back.
+    fn generated_wav(parts: &[u32]) -> Vec<i16> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut bytes = Cursor::new(Vec::new());
+        {
+            let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+            let mut i = 0u64;
+
+            for (n, &ms) in parts.iter().enumerate() {
+                for _ in 0..ms as u64 * 48 {
+                    let sample = if n % 2 == 0 {
+                        (8000.0 * (TAU * 300.0 * i as f64 / 48000.0).sin()) as i16
+                    } else {
+                        0
+                    };
+                    writer.write_sample(sample).unwrap();
+                    writer.write_sample(sample).unwrap();
+                    i += 1;
+                }
+            }
+            writer.finalize().unwrap();
+        }
+
+        bytes.set_position(0);
+        hound::WavReader::new(bytes)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect()
+    }
+
+    fn config() -> SegmenterConfig {
+        SegmenterConfig {
+            hangover_ms: 400,
+            min_ms: 300,
+            max_ms: 3000,
+            ..SegmenterConfig::default()
+        }
+    }
+
+    fn utterance_ms(utterance: &[i16]) -> u32 {
+        (utterance.len() / 2 / 48) as u32
+    }
+
+    /// Feeds audio a tick at a time, returning the length of each utterance found.
+    fn segment(segmenter: &mut Segmenter, samples: &[i16]) -> Vec<u32> {
+        samples
+            .chunks(TICK)
+            .filter_map(|chunk| segmenter.push(chunk))
+            .map(|utterance| utterance_ms(&utterance))
+            .collect()
+    }
+
+    #[test]
+    fn hangover_splits_phrases() {
+        let mut segmenter = Segmenter::new(config());
+
+        // A short pause stays within the phrase, a long one ends it.
+        let samples = generated_wav(&[500, 200, 500, 1000, 800, 1000]);
+        let utterances = segment(&mut segmenter, &samples);
+
+        assert_eq!(utterances, vec![1600, 1200]);
+    }
+
+    #[test]
+    fn short_sounds_are_dropped() {
+        let mut segmenter = Segmenter::new(config());
+
+        let samples = generated_wav(&[100, 1000, 600, 1000]);
+        let utterances = segment(&mut segmenter, &samples);
+
+        assert_eq!(utterances, vec![1000]);
+    }
+
+    #[test]
+    fn long_speech_is_cut() {
+        let mut segmenter = Segmenter::new(config());
+
+        let samples = generated_wav(&[7000, 1000]);
+        let utterances = segment(&mut segmenter, &samples);
+
+        assert_eq!(utterances, vec![3000, 3000, 1400]);
+    }
+
+    #[test]
+    fn speakers_are_segmented_independently() {
+        let mut segmenters = HashMap::new();
+        segmenters.insert(1u32, Segmenter::new(config()));
+        segmenters.insert(2u32, Segmenter::new(config()));
+
+        let first = generated_wav(&[1000, 1000]);
+        let second = generated_wav(&[0, 500, 600, 900]);
+        let mut utterances = Vec::new();
+
+        // Ticks arrive for both speakers in turn, as they would from Discord.
+        for (a, b) in first.chunks(TICK).zip(second.chunks(TICK)) {
+            for (ssrc, chunk) in [(1, a), (2, b)] {
+                if let Some(utterance) = segmenters.get_mut(&ssrc).unwrap().push(chunk) {
+                    utterances.push((ssrc, utterance_ms(&utterance)));
+                }
+            }
+        }
+
+        assert_eq!(utterances, vec![(1, 1400), (2, 1000)]);
+    }
+}
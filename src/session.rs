@@ -0,0 +1,369 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId};
+use serenity::builder::{CreateAttachment, CreateMessage};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+use serenity::prelude::{RwLock, TypeMap};
+use songbird::serenity::SongbirdKey;
+
+use crate::cfg::SESSIONS_DIR;
+use crate::dj::is_dj;
+use crate::guild::{get_guild_store, LoopMode};
+use crate::library::library_file;
+use crate::limits::{enqueue_checked, Rejection};
+use crate::music::get_or_join;
+use crate::state::{GuildStoreKey, PendingSessionsKey};
+use crate::store::save_json;
+use crate::track::{TrackInfo, TrackInfoKey, TrackSource};
+
+/// Bumped whenever the stored format changes incompatibly.
+pub const SESSION_VERSION: u32 = 1;
+
+/// A guild's queue as stored on disk, also what users export and import.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub guild_id: u64,
+    pub channel_id: Option<u64>,
+    /// Seconds into the first track.
+    pub position: u64,
+    pub volume: f32,
+    pub loop_mode: LoopMode,
+    pub tracks: Vec<TrackInfo>,
+    pub saved_at: i64,
+}
+
+fn session_file(guild_id: GuildId) -> String {
+    format!("{}/{}.json", SESSIONS_DIR, guild_id.get())
+}
+
+/// Snapshots a guild's queue, `None` if the bot isn't connected there.
+pub async fn snapshot(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> Option<Session> {
+    let (manager, settings) = {
+        let data = data.read().await;
+        let manager = data.get::<SongbirdKey>()?.clone();
+        let settings = data.get::<GuildStoreKey>()?.get(guild_id.get());
+        (manager, settings)
+    };
+
+    let handler_lock = manager.get(guild_id)?;
+    let (channel_id, handles) = {
+        let handler = handler_lock.lock().await;
+        (handler.current_channel(), handler.queue().current_queue())
+    };
+
+    let mut tracks = Vec::new();
+    for handle in &handles {
+        if let Some(track) = handle.typemap().read().await.get::<TrackInfoKey>() {
+            tracks.push(track.clone());
+        }
+    }
+
+    let position = match handles.first() {
+        Some(handle) => handle
+            .get_info()
+            .await
+            .map(|state| state.position.as_secs())
+            .unwrap_or(0),
+        None => 0,
+    };
+
+    Some(Session {
+        version: SESSION_VERSION,
+        guild_id: guild_id.get(),
+        channel_id: channel_id.map(|c| c.0.get()),
+        position,
+        volume: settings.volume,
+        loop_mode: settings.loop_mode,
+        tracks,
+        saved_at: Utc::now().timestamp(),
+    })
+}
+
+/// Persists a guild's queue, removing the saved session once the queue is empty.
+pub async fn save_session(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) {
+    let Some(session) = snapshot(data, guild_id).await else {
+        return;
+    };
+
+    let path = session_file(guild_id);
+
+    if session.tracks.is_empty() {
+        let _ = fs::remove_file(&path);
+        return;
+    }
+
+    if let Err(e) = save_json(&path, &session) {
+        error!("Failed to save session for {}: {:?}", guild_id, e);
+    }
+}
+
+/// Saves every connected guild's queue, used on shutdown.
+pub async fn save_all_sessions(data: &Arc<RwLock<TypeMap>>) {
+    let manager = data.read().await.get::<SongbirdKey>().cloned();
+
+    let Some(manager) = manager else {
+        return;
+    };
+
+    let guild_ids = manager
+        .iter()
+        .map(|(guild_id, _)| GuildId::new(guild_id.0.get()))
+        .collect::<Vec<_>>();
+
+    for guild_id in guild_ids {
+        save_session(data, guild_id).await;
+    }
+
+    info!("Saved sessions");
+}
+
+pub fn parse_session(bytes: &[u8]) -> Result<Session, Error> {
+    let session = serde_json::from_slice::<Session>(bytes)?;
+
+    if session.version > SESSION_VERSION {
+        return Err(Error::msg(format!(
+            "Unsupported session version {}",
+            session.version
+        )));
+    }
+
+    Ok(session)
+}
+
+/// Moves a saved session aside so it survives new queue changes, and offers
+/// to restore it.
+pub async fn offer_session(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) {
+    let Ok(bytes) = fs::read(session_file(guild_id)) else {
+        return;
+    };
+
+    let session = match parse_session(&bytes) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Failed to load session for {}: {:?}", guild_id, e);
+            return;
+        }
+    };
+
+    let _ = fs::remove_file(session_file(guild_id));
+
+    let count = session.tracks.len();
+    {
+        let data = ctx.data.read().await;
+        if let Some(pending) = data.get::<PendingSessionsKey>() {
+            pending.insert(guild_id.get(), session);
+        }
+    }
+
+    let _ = channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Found a saved queue with {} tracks, use `~resume-session` to restore it.",
+                count
+            ),
+        )
+        .await;
+}
+
+/// Forgets a guild's saved and pending sessions.
+async fn discard_session(ctx: &Context, guild_id: GuildId) {
+    let _ = fs::remove_file(session_file(guild_id));
+
+    let data = ctx.data.read().await;
+    if let Some(pending) = data.get::<PendingSessionsKey>() {
+        pending.remove(&guild_id.get());
+    }
+}
+
+/// Enqueues a session's tracks, resuming the first one where it left off.
+async fn restore(ctx: &Context, msg: &Message, session: Session) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+
+    // Otherwise joining would offer the same session again.
+    discard_session(ctx, guild_id).await;
+
+    let Some(handler_lock) = get_or_join(ctx, msg).await else {
+        return Ok(());
+    };
+
+    // Volume and loop mode are DJ settings, anyone else only gets the tracks back.
+    let dj = is_dj(ctx, guild_id, msg.author.id).await;
+    if dj {
+        get_guild_store(ctx).await.update(guild_id.get(), |s| {
+            s.volume = session.volume.clamp(0.0, 2.0);
+            s.loop_mode = session.loop_mode;
+        });
+    }
+
+    // Processed tracks are decoded as a stream which can't be seeked.
+    let processed = !get_guild_store(ctx)
+        .await
+        .get(guild_id.get())
+        .pcm_config()
+        .is_passthrough();
+    let resume_at = (session.position > 0).then(|| Duration::from_secs(session.position));
+
+    let total = session.tracks.len();
+    let was_empty = handler_lock.lock().await.queue().is_empty();
+    let mut restored = 0;
+    let mut rejected = None;
+
+    for (i, mut track) in session.tracks.into_iter().enumerate() {
+        // Imported files have to come from the library.
+        if let TrackSource::File(path) = &track.source {
+            match library_file(&path.display().to_string()) {
+                Some(path) => track.source = TrackSource::File(path),
+                None => continue,
+            }
+        }
+
+        let handle = match enqueue_checked(ctx, guild_id, &handler_lock, track).await {
+            Ok(handle) => handle,
+            Err(rejection @ (Rejection::UserQueueFull | Rejection::GuildQueueFull)) => {
+                rejected = Some(rejection);
+                break;
+            }
+            Err(rejection) => {
+                rejected = Some(rejection);
+                continue;
+            }
+        };
+        let first = i == 0 && was_empty && !processed;
+        if let Some(position) = resume_at.filter(|_| first) {
+            let seek = handle.seek(position);
+            tokio::spawn(async move {
+                if let Err(e) = seek.result_async().await {
+                    error!("Failed to resume restored track: {:?}", e);
+                }
+            });
+        }
+        restored += 1;
+    }
+
+    save_session(&ctx.data, guild_id).await;
+
+    let mut text = format!("Restored {} tracks.", restored);
+    if restored < total {
+        text = format!("{} Skipped {}.", text, total - restored);
+    }
+    if let Some(rejection) = rejected {
+        text = format!("{} {}", text, rejection.describe());
+    }
+    if !dj {
+        text = format!("{} Only DJs can restore the volume and loop mode.", text);
+    }
+    if was_empty && processed && resume_at.is_some() {
+        text = format!(
+            "{} Filters or normalization are on, so the first track starts from the beginning.",
+            text
+        );
+    }
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("resume-session")]
+pub async fn resume_session(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+
+    let pending = {
+        let data = ctx.data.read().await;
+        data.get::<PendingSessionsKey>()
+            .and_then(|pending| pending.remove(&guild_id.get()))
+            .map(|(_, session)| session)
+    };
+
+    let session = pending.or_else(|| {
+        fs::read(session_file(guild_id))
+            .ok()
+            .and_then(|bytes| parse_session(&bytes).ok())
+    });
+
+    match session {
+        Some(session) => restore(ctx, msg, session).await,
+        None => {
+            let _ = msg.channel_id.say(&ctx.http, "No saved queue.").await;
+            Ok(())
+        }
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("export-queue")]
+pub async fn export_queue(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+
+    let Some(session) = snapshot(&ctx.data, guild_id).await else {
+        let _ = msg.channel_id.say(&ctx.http, "Nothing queued.").await;
+        return Ok(());
+    };
+
+    let bytes = serde_json::to_vec_pretty(&session)?;
+    let file = CreateAttachment::bytes(bytes, format!("queue-{}.json", guild_id.get()));
+
+    let _ = msg
+        .channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(format!("Exported {} tracks.", session.tracks.len()))
+                .add_file(file),
+        )
+        .await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("import-queue")]
+pub async fn import_queue(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can import a queue.")
+            .await;
+        return Ok(());
+    }
+
+    let Some(attachment) = msg.attachments.first() else {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Attach an exported queue file.")
+            .await;
+        return Ok(());
+    };
+
+    let session = match attachment.download().await.map_err(Error::from) {
+        Ok(bytes) => parse_session(&bytes),
+        Err(e) => Err(e),
+    };
+
+    match session {
+        Ok(session) => restore(ctx, msg, session).await,
+        Err(e) => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, format!("Invalid queue file: {}", e))
+                .await;
+            Ok(())
+        }
+    }
+}
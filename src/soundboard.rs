@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serenity::all::GuildId;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+use songbird::input::File;
+use songbird::Call;
+
+use crate::cfg::{SOUNDBOARD_DIR, SOUNDBOARD_MAX_BYTES, SOUNDBOARD_MAX_CLIPS, SOUNDBOARD_MAX_SECS};
+use crate::dj::is_dj;
+use crate::ducking::speak;
+use crate::library::is_audio_file;
+use crate::music::get_or_join;
+use crate::pcm::decoded_duration;
+
+fn guild_dir(guild_id: GuildId) -> PathBuf {
+    Path::new(SOUNDBOARD_DIR).join(guild_id.get().to_string())
+}
+
+/// Sub-commands of `~sb`, a clip with one of these names could never be played.
+const RESERVED_NAMES: [&str; 3] = ["add", "list", "delete"];
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED_NAMES.contains(&name)
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Names of a guild's clips, sorted.
+pub fn list_clips(guild_id: GuildId) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(guild_dir(guild_id)) else {
+        return Vec::new();
+    };
+
+    let mut names = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect::<Vec<_>>();
+
+    names.sort();
+    names
+}
+
+fn clip_file(guild_id: GuildId, name: &str) -> Option<PathBuf> {
+    if !valid_name(name) {
+        return None;
+    }
+
+    fs::read_dir(guild_dir(guild_id))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.file_stem().and_then(|s| s.to_str()) == Some(name))
+}
+
+/// Finds a clip by how it sounds when spoken, `air-horn` matching "air horn".
+pub fn find_spoken_clip(guild_id: GuildId, spoken: &str) -> Option<String> {
+    let spoken = spoken
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    list_clips(guild_id)
+        .into_iter()
+        .find(|name| name.replace(['-', '_'], " ") == spoken)
+}
+
+/// Plays a clip over whatever's playing, leaving the queue alone.
+pub async fn play_clip(ctx: &Context, guild_id: GuildId, handler: &mut Call, name: &str) {
+    if let Some(path) = clip_file(guild_id, name) {
+        speak(ctx, guild_id, handler, File::new(path).into()).await;
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("soundboard")]
+#[sub_commands(add, list, delete)]
+#[description = "Plays a soundboard clip: ~sb <name>, ~sb add <name>, ~sb list, ~sb delete <name>"]
+pub async fn sb(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let name = args.message().trim().to_lowercase();
+
+    if name.is_empty() {
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                "Usage: ~sb <name>, ~sb add <name>, ~sb list, ~sb delete <name>",
+            )
+            .await;
+        return Ok(());
+    }
+
+    if clip_file(guild_id, &name).is_none() {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, format!("No clip named {}.", name))
+            .await;
+        return Ok(());
+    }
+
+    let Some(handler_lock) = get_or_join(ctx, msg).await else {
+        return Ok(());
+    };
+
+    let mut handler = handler_lock.lock().await;
+    play_clip(ctx, guild_id, &mut handler, &name).await;
+
+    Ok(())
+}
+
+/// Saves the attached audio file as a clip.
+#[command]
+#[only_in(guilds)]
+pub async fn add(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let name = args.message().trim().to_lowercase();
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can add clips.")
+            .await;
+        return Ok(());
+    }
+
+    if !valid_name(&name) {
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                "Clip names can only use lowercase letters, digits, - and _, up to 32 characters, and can't be add, list or delete.",
+            )
+            .await;
+        return Ok(());
+    }
+
+    let Some(attachment) = msg.attachments.iter().find(|a| is_audio_file(&a.filename)) else {
+        let _ = msg.channel_id.say(&ctx.http, "Attach an audio file.").await;
+        return Ok(());
+    };
+
+    if attachment.size > SOUNDBOARD_MAX_BYTES {
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                format!("Clips can be at most {} KiB.", SOUNDBOARD_MAX_BYTES / 1024),
+            )
+            .await;
+        return Ok(());
+    }
+
+    let existing = clip_file(guild_id, &name);
+    if existing.is_none() && list_clips(guild_id).len() >= SOUNDBOARD_MAX_CLIPS {
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "The soundboard is full, {} clips max.",
+                    SOUNDBOARD_MAX_CLIPS
+                ),
+            )
+            .await;
+        return Ok(());
+    }
+
+    let extension = Path::new(&attachment.filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let bytes = attachment.download().await?;
+
+    let checked = bytes.clone();
+    let check_ext = extension.clone();
+    let duration = tokio::task::spawn_blocking(move || {
+        decoded_duration(checked, &check_ext, SOUNDBOARD_MAX_SECS)
+    })
+    .await?;
+
+    let text = match duration {
+        Ok(secs) if secs > SOUNDBOARD_MAX_SECS => {
+            format!("Clips can be at most {} seconds long.", SOUNDBOARD_MAX_SECS)
+        }
+        Ok(secs) => {
+            if let Some(existing) = existing {
+                fs::remove_file(existing)?;
+            }
+
+            let dir = guild_dir(guild_id);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(format!("{}.{}", name, extension)), bytes)?;
+
+            format!("Added {} ({:.1}s).", name, secs)
+        }
+        Err(e) => format!("Couldn't read that file: {}", e),
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn list(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let clips = list_clips(msg.guild_id.unwrap());
+
+    let text = if clips.is_empty() {
+        "No clips yet, add one with `~sb add <name>` and an attached file.".to_string()
+    } else {
+        clips
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+pub async fn delete(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let name = args.message().trim().to_lowercase();
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can delete clips.")
+            .await;
+        return Ok(());
+    }
+
+    let text = match clip_file(guild_id, &name) {
+        Some(path) => {
+            fs::remove_file(path)?;
+            format!("Deleted {}.", name)
+        }
+        None => format!("No clip named {}.", name),
+    };
+
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_command_names_are_reserved() {
+        assert!(valid_name("airhorn"));
+        assert!(!valid_name("add"));
+        assert!(!valid_name("list"));
+        assert!(!valid_name("delete"));
+    }
+}
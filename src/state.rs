@@ -1,15 +1,94 @@
 use std::sync::Arc;
 
+use dashmap::DashMap;
 use reqwest::Client as HttpClient;
 use serenity::gateway::ShardManager;
 use songbird::typemap::TypeMapKey;
 
+use crate::audio_cache::AudioCache;
+use crate::bot::Bot;
+use crate::dj::SkipVotes;
+use crate::ducking::Ducking;
+use crate::guild::GuildStore;
+use crate::limits::Blocklist;
+use crate::playlist::PlaylistStore;
+use crate::plays::PlayStore;
+use crate::radio::Radio;
+use crate::search::SearchProvider;
+use crate::session::Session;
+
 pub struct HttpKey;
 
 impl TypeMapKey for HttpKey {
     type Value = HttpClient;
 }
 
+pub struct AudioCacheKey;
+
+impl TypeMapKey for AudioCacheKey {
+    type Value = Arc<AudioCache>;
+}
+
+pub struct BlocklistKey;
+
+impl TypeMapKey for BlocklistKey {
+    type Value = Arc<Blocklist>;
+}
+
+pub struct BotKey;
+
+impl TypeMapKey for BotKey {
+    type Value = Bot;
+}
+
+pub struct DuckingKey;
+
+impl TypeMapKey for DuckingKey {
+    type Value = Arc<DashMap<u64, Arc<Ducking>>>;
+}
+
+pub struct GuildStoreKey;
+
+impl TypeMapKey for GuildStoreKey {
+    type Value = Arc<GuildStore>;
+}
+
+pub struct PendingSessionsKey;
+
+impl TypeMapKey for PendingSessionsKey {
+    type Value = Arc<DashMap<u64, Session>>;
+}
+
+pub struct PlaylistStoreKey;
+
+impl TypeMapKey for PlaylistStoreKey {
+    type Value = Arc<PlaylistStore>;
+}
+
+pub struct PlayStoreKey;
+
+impl TypeMapKey for PlayStoreKey {
+    type Value = Arc<PlayStore>;
+}
+
+pub struct RadioKey;
+
+impl TypeMapKey for RadioKey {
+    type Value = Arc<DashMap<u64, Radio>>;
+}
+
+pub struct SearchKey;
+
+impl TypeMapKey for SearchKey {
+    type Value = Arc<dyn SearchProvider>;
+}
+
+pub struct SkipVotesKey;
+
+impl TypeMapKey for SkipVotesKey {
+    type Value = Arc<DashMap<u64, SkipVotes>>;
+}
+
 pub struct ShardManagerContainer;
 
 impl TypeMapKey for ShardManagerContainer {
@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Error;
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reads a JSON file, falling back to the default value if it's missing or invalid.
+pub fn load_json<T: DeserializeOwned + Default>(path: &str) -> T {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+            error!("Failed to parse {}: {:?}", path, e);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+/// Numbers temporary files, so concurrent saves of one file don't write to the same one.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes a JSON file atomically, creating parent directories as needed.
+pub fn save_json<T: Serialize>(path: &str, value: &T) -> Result<(), Error> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let counter = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = format!("{path}.{}.{counter}.tmp", process::id());
+
+    let result =
+        fs::write(&tmp, serde_json::to_vec_pretty(value)?).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    Ok(result?)
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::Error;
+use log::info;
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use serenity::async_trait;
+
+use crate::openai::{build_multipart_client, OPENAI_API_URL};
+
+/// Hints passed along with each utterance.
+#[derive(Clone, Debug, Default)]
+pub struct TranscribeOptions {
+    /// ISO-639-1 code of the spoken language, detected when unset.
+    pub language: Option<String>,
+    /// Text the model should expect, biasing it towards names and commands in it.
+    pub prompt: Option<String>,
+}
+
+#[async_trait]
+pub trait SpeechToText: Send + Sync {
+    /// Transcribes a WAV file.
+    async fn transcribe(&self, wav: Vec<u8>, options: &TranscribeOptions) -> Result<String, Error>;
+}
+
+/// Posts to an OpenAI-style `/audio/transcriptions` endpoint.
+async fn post_transcription(
+    client: &Client,
+    base_url: &str,
+    model: &str,
+    wav: Vec<u8>,
+    options: &TranscribeOptions,
+) -> Result<String, Error> {
+    let mut form = Form::new()
+        .part(
+            "file",
+            Part::bytes(wav)
+                .file_name("utterance.wav")
+                .mime_str("audio/wav")?,
+        )
+        .part("model", Part::text(model.to_string()));
+
+    if let Some(language) = &options.language {
+        form = form.part("language", Part::text(language.clone()));
+    }
+    if let Some(prompt) = &options.prompt {
+        form = form.part("prompt", Part::text(prompt.clone()));
+    }
+
+    let data = client
+        .post(format!(
+            "{}/audio/transcriptions",
+            base_url.trim_end_matches('/')
+        ))
+        .multipart(form)
+        .send()
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+
+    data["text"]
+        .as_str()
+        .map(|text| text.trim().to_string())
+        .ok_or_else(|| Error::msg("Failed to transcribe audio"))
+}
+
+/// OpenAI's hosted `whisper-1`, requires `OPENAI_API_KEY`.
+pub struct OpenAiWhisper {
+    client: Client,
+}
+
+impl OpenAiWhisper {
+    pub fn new(api_key: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: build_multipart_client(api_key)?,
+        })
+    }
+}
+
+#[async_trait]
+impl SpeechToText for OpenAiWhisper {
+    async fn transcribe(&self, wav: Vec<u8>, options: &TranscribeOptions) -> Result<String, Error> {
+        post_transcription(&self.client, OPENAI_API_URL, "whisper-1", wav, options).await
+    }
+}
+
+/// A self-hosted server with an OpenAI-compatible API, like faster-whisper-server
+/// or whisper.cpp's server.
+pub struct LocalWhisper {
+    client: Client,
+    base_url: String,
+    model: String,
+}
+
+impl LocalWhisper {
+    pub fn new(base_url: String, model: String) -> Self {
+        Self {
+            client: Client::new(),
+            base_url,
+            model,
+        }
+    }
+}
+
+#[async_trait]
+impl SpeechToText for LocalWhisper {
+    async fn transcribe(&self, wav: Vec<u8>, options: &TranscribeOptions) -> Result<String, Error> {
+        post_transcription(&self.client, &self.base_url, &self.model, wav, options).await
+    }
+}
+
+/// Picks a backend from `STT_PROVIDER` (`openai` or `local`), defaulting to OpenAI.
+/// The local server is found at `STT_BASE_URL` and asked for `STT_MODEL`.
+pub fn build_speech_to_text() -> Arc<dyn SpeechToText> {
+    let provider = env::var("STT_PROVIDER").unwrap_or_default();
+
+    match provider.as_str() {
+        "local" => {
+            let base_url = env::var("STT_BASE_URL").expect("STT_BASE_URL not set");
+            let model = env::var("STT_MODEL")
+                .ok()
+                .filter(|m| !m.is_empty())
+                .unwrap_or_else(|| "whisper-1".to_string());
+
+            info!("Speech to text: {} ({})", base_url, model);
+            Arc::new(LocalWhisper::new(base_url, model))
+        }
+        _ => {
+            let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
+
+            info!("Speech to text: OpenAI");
+            Arc::new(OpenAiWhisper::new(&api_key).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::voice_command::{parse, VoiceCommand};
+
+    /// Hands out canned transcripts in order, for exercising voice commands
+    /// without a microphone or network access.
+    pub struct FakeSpeechToText {
+        transcripts: Mutex<VecDeque<String>>,
+    }
+
+    impl FakeSpeechToText {
+        pub fn new(transcripts: Vec<String>) -> Self {
+            Self {
+                transcripts: Mutex::new(transcripts.into()),
+            }
+        }
+
+        /// Reads one transcript per line of a fixture file.
+        pub fn from_fixture(path: &str) -> Result<Self, Error> {
+            let transcripts = fs::read_to_string(path)?
+                .lines()
+                .map(|line| line.to_string())
+                .collect();
+
+            Ok(Self::new(transcripts))
+        }
+    }
+
+    #[async_trait]
+    impl SpeechToText for FakeSpeechToText {
+        async fn transcribe(
+            &self,
+            _wav: Vec<u8>,
+            _options: &TranscribeOptions,
+        ) -> Result<String, Error> {
+            self.transcripts
+                .lock()
+                .ok()
+                .and_then(|mut transcripts| transcripts.pop_front())
+                .ok_or_else(|| Error::msg("No transcripts left"))
+        }
+    }
+
+    #[tokio::test]
+    async fn fixture_transcripts_parse_to_commands() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/transcripts.txt");
+        let stt = FakeSpeechToText::from_fixture(path).unwrap();
+        let options = TranscribeOptions::default();
+
+        let expected = [
+            Some(VoiceCommand::Play("despacito".to_string())),
+            Some(VoiceCommand::Skip),
+            Some(VoiceCommand::Pause),
+            Some(VoiceCommand::VolumeUp),
+            Some(VoiceCommand::NowPlaying),
+            Some(VoiceCommand::Sound("airhorn".to_string())),
+            None,
+            Some(VoiceCommand::Chat("Adam, how was your day?".to_string())),
+        ];
+
+        for command in expected {
+            let transcript = stt.transcribe(Vec::new(), &options).await.unwrap();
+            assert_eq!(parse(&transcript), command, "{}", transcript);
+        }
+
+        assert!(stt.transcribe(Vec::new(), &options).await.is_err());
+    }
+}
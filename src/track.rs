@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use songbird::input::{File, HttpRequest, Input, YoutubeDl};
+use songbird::typemap::TypeMapKey;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TrackSource {
+    /// Anything yt-dlp can resolve.
+    Youtube(String),
+    /// A direct link to an audio file, e.g. a Discord attachment.
+    Http(String),
+    /// A file on local disk.
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub title: String,
+    pub artist: Option<String>,
+    pub duration: Option<u64>,
+    pub source: TrackSource,
+    pub requester: u64,
+    /// Picked by autoplay rather than requested.
+    #[serde(default)]
+    pub radio: bool,
+}
+
+impl TrackInfo {
+    pub fn new(title: &str, source: TrackSource, requester: u64) -> Self {
+        Self {
+            title: title.to_string(),
+            artist: None,
+            duration: None,
+            source,
+            requester,
+            radio: false,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        match &self.source {
+            TrackSource::Youtube(url) | TrackSource::Http(url) => url.clone(),
+            TrackSource::File(path) => path.display().to_string(),
+        }
+    }
+
+    /// Lazy input for this track, nothing is fetched until it's about to play.
+    pub fn input(&self, client: HttpClient) -> Input {
+        match &self.source {
+            TrackSource::Youtube(url) => YoutubeDl::new(client, url.clone()).into(),
+            TrackSource::Http(url) => HttpRequest::new(client, url.clone()).into(),
+            TrackSource::File(path) => File::new(path.clone()).into(),
+        }
+    }
+
+    pub fn display(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+}
+
+pub struct TrackInfoKey;
+
+impl TypeMapKey for TrackInfoKey {
+    type Value = TrackInfo;
+}
+
+pub fn format_duration(secs: u64) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
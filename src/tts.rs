@@ -0,0 +1,333 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::Error;
+use log::info;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::channel::Message;
+
+use crate::cfg::{TTS_FORMAT, TTS_MODEL, TTS_SPEED, TTS_VOICE, TTS_VOICES};
+use crate::dj::is_dj;
+use crate::guild::get_guild_store;
+use crate::openai::{build_json_client, SpeechRequest, OPENAI_API_URL};
+
+/// Formats every backend can return and songbird can decode.
+const FORMATS: [&str; 4] = ["mp3", "opus", "flac", "wav"];
+
+/// How the bot sounds in a guild.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TtsSettings {
+    pub model: String,
+    pub voice: String,
+    pub speed: f32,
+    pub format: String,
+}
+
+impl Default for TtsSettings {
+    fn default() -> Self {
+        Self {
+            model: TTS_MODEL.to_string(),
+            voice: TTS_VOICE.to_string(),
+            speed: TTS_SPEED,
+            format: TTS_FORMAT.to_string(),
+        }
+    }
+}
+
+impl TtsSettings {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({}, {:.2}x, {})",
+            self.voice, self.model, self.speed, self.format
+        )
+    }
+
+    /// File extension for the returned audio, opus comes in an Ogg container.
+    pub fn extension(&self) -> &str {
+        match self.format.as_str() {
+            "opus" => "ogg",
+            format => format,
+        }
+    }
+}
+
+#[async_trait]
+pub trait TextToSpeech: Send + Sync {
+    /// Speaks `text`, returning the encoded audio.
+    async fn synthesize(&self, text: &str, settings: &TtsSettings) -> Result<Vec<u8>, Error>;
+}
+
+/// Posts to an OpenAI-style `/audio/speech` endpoint.
+async fn post_speech(
+    client: &Client,
+    base_url: &str,
+    text: &str,
+    settings: &TtsSettings,
+) -> Result<Vec<u8>, Error> {
+    let res = client
+        .post(format!("{}/audio/speech", base_url.trim_end_matches('/')))
+        .json(&SpeechRequest {
+            model: settings.model.clone(),
+            input: text.to_string(),
+            voice: settings.voice.clone(),
+            speed: settings.speed,
+            response_format: settings.format.clone(),
+        })
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(Error::msg(format!(
+            "Failed to generate audio: {}",
+            res.status()
+        )));
+    }
+
+    Ok(res.bytes().await?.to_vec())
+}
+
+/// OpenAI's hosted speech models, requires `OPENAI_API_KEY`.
+pub struct OpenAiTts {
+    client: Client,
+}
+
+impl OpenAiTts {
+    pub fn new(api_key: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: build_json_client(api_key)?,
+        })
+    }
+}
+
+#[async_trait]
+impl TextToSpeech for OpenAiTts {
+    async fn synthesize(&self, text: &str, settings: &TtsSettings) -> Result<Vec<u8>, Error> {
+        post_speech(&self.client, OPENAI_API_URL, text, settings).await
+    }
+}
+
+/// A self-hosted server with an OpenAI-compatible speech API, like
+/// openedai-speech wrapping Piper voices.
+pub struct LocalTts {
+    client: Client,
+    base_url: String,
+}
+
+impl LocalTts {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            client: Client::new(),
+            base_url,
+        }
+    }
+}
+
+#[async_trait]
+impl TextToSpeech for LocalTts {
+    async fn synthesize(&self, text: &str, settings: &TtsSettings) -> Result<Vec<u8>, Error> {
+        post_speech(&self.client, &self.base_url, text, settings).await
+    }
+}
+
+/// Picks a backend from `TTS_PROVIDER` (`openai` or `local`), defaulting to OpenAI.
+/// The local server is found at `TTS_BASE_URL`.
+pub fn build_text_to_speech() -> Arc<dyn TextToSpeech> {
+    let provider = env::var("TTS_PROVIDER").unwrap_or_default();
+
+    match provider.as_str() {
+        "local" => {
+            let base_url = env::var("TTS_BASE_URL").expect("TTS_BASE_URL not set");
+
+            info!("Text to speech: {}", base_url);
+            Arc::new(LocalTts::new(base_url))
+        }
+        _ => {
+            let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
+
+            info!("Text to speech: OpenAI");
+            Arc::new(OpenAiTts::new(&api_key).unwrap())
+        }
+    }
+}
+
+/// Applies `~voice` arguments, returning `None` if they don't make sense.
+fn apply(settings: &mut TtsSettings, name: &str, value: Option<&str>) -> Option<()> {
+    match (name, value) {
+        ("speed", Some(value)) => {
+            let speed = value.trim_end_matches('x').parse::<f32>().ok()?;
+            settings.speed = (0.25..=4.0).contains(&speed).then_some(speed)?;
+        }
+        ("model", Some(value)) => settings.model = value.to_string(),
+        ("format", Some(value)) => {
+            settings.format = FORMATS.contains(&value).then(|| value.to_string())?;
+        }
+        ("reset", None) => *settings = TtsSettings::default(),
+        (voice, None) if TTS_VOICES.is_empty() || TTS_VOICES.contains(&voice) => {
+            settings.voice = voice.to_string();
+        }
+        _ => return None,
+    }
+
+    Some(())
+}
+
+/// Picks how the bot sounds, e.g. `~voice nova`, `~voice speed 1.2`.
+#[command]
+#[only_in(guilds)]
+#[description = "Shows or changes the bot's voice: ~voice <name>, ~voice speed|model|format <value>, ~voice reset"]
+pub async fn voice(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+    let mut settings = store.get(guild_id.get()).tts;
+
+    if args.is_empty() {
+        let mut text = format!("Voice: {}", settings.describe());
+        if !TTS_VOICES.is_empty() {
+            text.push_str(&format!("\nVoices: {}", TTS_VOICES.join(", ")));
+        }
+
+        let _ = msg.channel_id.say(&ctx.http, text).await;
+        return Ok(());
+    }
+
+    if !is_dj(ctx, guild_id, msg.author.id).await {
+        let _ = msg
+            .channel_id
+            .say(&ctx.http, "Only DJs can change the voice.")
+            .await;
+        return Ok(());
+    }
+
+    let name = args.single::<String>()?.to_lowercase();
+    let value = args.single::<String>().ok().map(|v| v.to_lowercase());
+
+    if apply(&mut settings, &name, value.as_deref()).is_none() {
+        let voices = if TTS_VOICES.is_empty() {
+            "name".to_string()
+        } else {
+            TTS_VOICES.join("|")
+        };
+        let _ = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "Usage: ~voice <{}>, ~voice speed <0.25-4>, ~voice model <name>, ~voice format <{}>, ~voice reset",
+                    voices,
+                    FORMATS.join("|")
+                ),
+            )
+            .await;
+        return Ok(());
+    }
+
+    let settings = store.update(guild_id.get(), |s| s.tts = settings).tts;
+
+    let _ = msg
+        .channel_id
+        .say(&ctx.http, format!("Voice: {}", settings.describe()))
+        .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use std::io::Cursor;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+    use crate::pcm::decoded_duration;
+
+    /// Answers with a WAV of silence, or a beep, about as long as the text
+    /// would take to say, for exercising replies without network access.
+    pub struct FakeTts {
+        beep: bool,
+    }
+
+    impl FakeTts {
+        const SAMPLE_RATE: u32 = 24000;
+        const MS_PER_CHAR: u32 = 60;
+
+        pub fn silent() -> Self {
+            Self { beep: false }
+        }
+
+        pub fn beep() -> Self {
+            Self { beep: true }
+        }
+    }
+
+    #[async_trait]
+    impl TextToSpeech for FakeTts {
+        async fn synthesize(&self, text: &str, settings: &TtsSettings) -> Result<Vec<u8>, Error> {
+            let spec = WavSpec {
+                channels: 1,
+                sample_rate: Self::SAMPLE_RATE,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+
+            let ms = (text.chars().count() as u32 * Self::MS_PER_CHAR).max(200) as f32
+                / settings.speed.max(0.25);
+            let samples = (ms / 1000.0 * Self::SAMPLE_RATE as f32) as u32;
+
+            let mut wav = Cursor::new(Vec::new());
+            let mut writer = WavWriter::new(&mut wav, spec)?;
+
+            for i in 0..samples {
+                let sample = if self.beep {
+                    (TAU * 440.0 * i as f32 / Self::SAMPLE_RATE as f32).sin() * 0.25
+                } else {
+                    0.0
+                };
+                writer.write_sample((sample * i16::MAX as f32) as i16)?;
+            }
+
+            writer.finalize()?;
+
+            Ok(wav.into_inner())
+        }
+    }
+
+    #[tokio::test]
+    async fn fake_speech_decodes_to_its_length() {
+        let settings = TtsSettings {
+            format: "wav".to_string(),
+            ..TtsSettings::default()
+        };
+
+        for tts in [FakeTts::silent(), FakeTts::beep()] {
+            // 50 characters at 60ms each.
+            let bytes = tts.synthesize(&"a".repeat(50), &settings).await.unwrap();
+            let secs = decoded_duration(bytes, settings.extension(), f64::INFINITY).unwrap();
+
+            assert!((secs - 3.0).abs() < 0.01, "{}", secs);
+        }
+    }
+
+    #[tokio::test]
+    async fn faster_speech_is_shorter() {
+        let settings = TtsSettings {
+            format: "wav".to_string(),
+            speed: 2.0,
+            ..TtsSettings::default()
+        };
+
+        let bytes = FakeTts::beep()
+            .synthesize(&"a".repeat(50), &settings)
+            .await
+            .unwrap();
+        let secs = decoded_duration(bytes, settings.extension(), f64::INFINITY).unwrap();
+
+        assert!((secs - 1.5).abs() < 0.01, "{}", secs);
+    }
+}
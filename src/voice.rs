@@ -1,260 +1,499 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::{env, fs};
+use std::time::Duration as StdDuration;
 
 use anyhow::Error;
 use chrono::{DateTime, Duration, Utc};
 use dashmap::DashMap;
 use hound::{SampleFormat, WavSpec, WavWriter};
-use log::info;
-use reqwest::multipart::{Form, Part};
-use serenity::all::GuildId;
+use log::{info, warn};
+use serenity::all::{ChannelId, GuildId, UserId as SerenityUserId};
 use serenity::async_trait;
 use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
 use serenity::gateway::ActivityData;
 use serenity::model::channel::Message;
 use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
 use songbird::input::Input;
 use songbird::model::id::UserId;
 use songbird::model::payload::{ClientDisconnect, Speaking};
-use songbird::{CoreEvent, Event, EventContext as Ctx, EventHandler};
-
-use crate::bot::Bot;
-use crate::cfg::SYS_PROMPT;
-use crate::music::find_song;
-use crate::openai::{
-    build_json_client, build_multipart_client, ChatMessage, ChatRequest, SpeechRequest,
-    OPENAI_API_URL,
+use songbird::tracks::TrackHandle;
+use songbird::{Call, CoreEvent, Event, EventContext as Ctx, EventHandler, TrackEvent};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::bot::{get_bot, Bot};
+use crate::captions::Captions;
+use crate::cfg::{
+    BARGE_IN_MS, BOT_ID, RECORDINGS_DIR, RECORD_UTTERANCES, STT_LANGUAGE, STT_PROMPT, SYS_PROMPT,
+    VOICE_QUEUE_CAPACITY, VOICE_VOLUME_STEP, WATCHDOG_INTERVAL_SECS,
 };
-
+use crate::dj::{is_dj, vote_skip};
+use crate::ducking::{get_ducking, speak};
+use crate::guild::get_guild_store;
+use crate::limits::{check_content, check_queue, check_search};
+use crate::music::{clear_upcoming, enqueue, find_song, set_volume};
+use crate::pcm::decoded_duration;
+use crate::segmenter::{Segmenter, SegmenterConfig};
+use crate::session::{offer_session, save_session};
+use crate::soundboard::{find_spoken_clip, play_clip};
+use crate::stt::TranscribeOptions;
+use crate::track::TrackInfoKey;
+use crate::voice_command::{self, VoiceCommand};
+use crate::watchdog::Watchdog;
+
+/// Length of the audio in each `VoiceTick`.
+const TICK_MS: u32 = 20;
+
+const TRANSCRIBE_SAMPLE_RATE: u32 = 16000;
+/// Discord's 48kHz over `TRANSCRIBE_SAMPLE_RATE`.
+const DOWNSAMPLE_FACTOR: usize = 3;
+
+/// How long past its length a reply counts as playing if its end event is lost.
+const REPLY_GRACE_SECS: i64 = 2;
+
+/// Cuts incoming audio into utterances and hands them to the guild's
+/// `VoiceWorker`, never waiting on anything itself.
 #[derive(Clone)]
 struct Receiver {
     ctx: Context,
     guild_id: GuildId,
-    chat_model: String,
-    json_client: reqwest::Client,
-    multipart_client: reqwest::Client,
     controller: Arc<VoiceController>,
 }
 
+struct VoiceController {
+    known_ssrcs: DashMap<u32, UserId>,
+    speakers: DashMap<u32, Speaker>,
+    utterances: mpsc::Sender<Utterance>,
+    dropped: AtomicU64,
+    last_reply: Arc<Mutex<Option<VoiceReply>>>,
+}
+
+struct Utterance {
+    user_id: u64,
+    samples: Vec<i16>,
+}
+
+/// Transcribes utterances and acts on them one at a time, stopping once the
+/// `Receiver` is dropped.
+struct VoiceWorker {
+    ctx: Context,
+    guild_id: GuildId,
+    bot: Bot,
+    captions: Captions,
+    last_reply: Arc<Mutex<Option<VoiceReply>>>,
+}
+
+/// The bot's last spoken reply, playing until its track ends. `expires` is
+/// a fallback in case the end event never arrives.
 struct VoiceReply {
-    timestamp: DateTime<Utc>,
-    duration: Duration,
+    handle: TrackHandle,
+    playing: Arc<AtomicBool>,
+    expires: DateTime<Utc>,
+    /// Set when someone talked over the reply and it was cut off.
+    interrupted: bool,
 }
 
-struct VoiceController {
-    last_tick_was_empty: AtomicBool,
-    known_ssrcs: DashMap<u32, UserId>,
-    accumulator: DashMap<u32, Slice>,
-    last_reply: Mutex<Option<VoiceReply>>,
+impl VoiceReply {
+    fn playing(&self) -> bool {
+        self.playing.load(Ordering::SeqCst) && Utc::now() < self.expires
+    }
+}
+
+/// Marks a reply as finished once its track ends or fails.
+struct ReplyEnded(Arc<AtomicBool>);
+
+#[async_trait]
+impl EventHandler for ReplyEnded {
+    async fn act(&self, _ctx: &Ctx<'_>) -> Option<Event> {
+        self.0.store(false, Ordering::SeqCst);
+
+        Some(Event::Cancel)
+    }
 }
 
-struct Slice {
+struct Speaker {
     user_id: u64,
-    bytes: Vec<i16>,
-    timestamp: DateTime<Utc>,
+    segmenter: Segmenter,
+}
+
+impl Speaker {
+    fn new(user_id: u64) -> Self {
+        Self {
+            user_id,
+            segmenter: Segmenter::new(SegmenterConfig::default()),
+        }
+    }
 }
 
 impl Receiver {
-    pub fn new(ctx: Context, guild_id: GuildId) -> Self {
-        let openai_api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-        let chat_model = env::var("MODEL").expect("MODEL not set");
-        let json_client = build_json_client(&openai_api_key).unwrap();
-        let multipart_client = build_multipart_client(&openai_api_key).unwrap();
+    /// Creates the receiver and spawns the worker it feeds.
+    pub fn new(ctx: Context, guild_id: GuildId, bot: Bot) -> Self {
+        let (sender, receiver) = mpsc::channel(VOICE_QUEUE_CAPACITY);
+        let last_reply = Arc::new(Mutex::new(None));
+
+        tokio::spawn(
+            VoiceWorker::new(ctx.clone(), guild_id, bot, last_reply.clone()).run(receiver),
+        );
 
         Self {
             ctx,
             guild_id,
-            chat_model,
-            json_client,
-            multipart_client,
             controller: Arc::new(VoiceController {
-                last_tick_was_empty: AtomicBool::default(),
                 known_ssrcs: DashMap::new(),
-                accumulator: DashMap::new(),
-                last_reply: Mutex::new(None),
+                speakers: DashMap::new(),
+                utterances: sender,
+                dropped: AtomicU64::new(0),
+                last_reply,
             }),
         }
     }
 
-    async fn process(&self, slice: &mut Slice) -> Result<(), Error> {
-        if let Ok(mut last_reply) = self.controller.last_reply.lock() {
-            if let Some(reply) = last_reply.take() {
-                let elapsed = Utc::now() - reply.timestamp;
-                let remaining = reply.duration - elapsed;
+    /// Cuts off the bot's reply if the guild lets people talk over it.
+    async fn barge_in(&self) {
+        let handle = match self.controller.last_reply.lock() {
+            Ok(reply) => match reply.as_ref() {
+                Some(reply) if reply.playing() && !reply.interrupted => reply.handle.clone(),
+                _ => return,
+            },
+            Err(_) => return,
+        };
 
-                if remaining > Duration::milliseconds(0) || slice.bytes.len() < 48000 {
-                    slice.timestamp = Utc::now();
-                    slice.bytes.clear();
+        if !get_guild_store(&self.ctx)
+            .await
+            .get(self.guild_id.get())
+            .barge_in
+        {
+            return;
+        }
 
-                    return Ok(());
+        info!("Reply in {} interrupted", self.guild_id);
+
+        if let Ok(mut reply) = self.controller.last_reply.lock() {
+            if let Some(reply) = reply.as_mut() {
+                reply.interrupted = true;
+            }
+        }
+
+        let _ = handle.stop();
+    }
+
+    /// Queues an utterance for the worker, dropping it if the worker is behind.
+    fn submit(&self, utterance: Utterance) {
+        let utterances = &self.controller.utterances;
+
+        match utterances.try_send(utterance) {
+            Ok(()) => {
+                let waiting = utterances.max_capacity() - utterances.capacity();
+                if waiting > 1 {
+                    info!("{} utterances waiting in {}", waiting, self.guild_id);
                 }
             }
+            Err(TrySendError::Full(utterance)) => {
+                let dropped = self.controller.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "Voice worker for {} is behind, dropped utterance from {} ({} dropped so far)",
+                    self.guild_id, utterance.user_id, dropped
+                );
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+impl VoiceWorker {
+    fn new(
+        ctx: Context,
+        guild_id: GuildId,
+        bot: Bot,
+        last_reply: Arc<Mutex<Option<VoiceReply>>>,
+    ) -> Self {
+        Self {
+            captions: Captions::start(ctx.clone(), guild_id),
+            ctx,
+            guild_id,
+            bot,
+            last_reply,
         }
+    }
 
-        let filename = format!("cache/{}_{}.wav", slice.user_id, Utc::now().timestamp());
+    async fn run(mut self, mut utterances: mpsc::Receiver<Utterance>) {
+        while let Some(Utterance { user_id, samples }) = utterances.recv().await {
+            let started = Utc::now();
 
-        self.save(&slice.bytes, &filename);
+            if let Err(e) = self.process(user_id, samples).await {
+                info!("Processing error: {:?}", e);
+            }
 
-        slice.timestamp = Utc::now();
-        slice.bytes.clear();
+            info!(
+                "Processed utterance from {} in {}ms",
+                user_id,
+                (Utc::now() - started).num_milliseconds()
+            );
+        }
 
-        if let Ok(text) = self.transcribe(&filename).await {
-            let text = text.to_lowercase();
-            let mentioned = ["adam", "add", "i don't"].iter().any(|s| text.contains(s));
+        info!("Voice worker for {} stopped", self.guild_id);
+    }
 
-            match text
-                .replace("adam", "")
-                .trim()
-                .chars()
-                .filter(|&c| c != ',' && c != '.' && c != '!')
-                .collect::<String>()
-                .as_str()
-            {
-                t if t.starts_with("play") || t.starts_with("clay") || t.starts_with("lay") => {
-                    let search = t.split_whitespace().skip(1).collect::<Vec<_>>().join(" ");
+    /// Handles one speaker's finished utterance.
+    async fn process(&mut self, user_id: u64, samples: Vec<i16>) -> Result<(), Error> {
+        if !self.should_process(&samples) {
+            return Ok(());
+        }
 
-                    info!("Searching for {}", search);
+        let wav = encode_utterance(&samples)?;
 
-                    let manager = songbird::get(&self.ctx).await.unwrap().clone();
+        if RECORD_UTTERANCES {
+            record(user_id, &wav);
+        }
 
-                    if let Some(handler_lock) = manager.get(self.guild_id) {
-                        let mut handler = handler_lock.lock().await;
+        let Ok(text) = self.transcribe(wav).await else {
+            return Ok(());
+        };
+        let name = display_name(&self.ctx, self.guild_id, SerenityUserId::new(user_id));
+        let command = voice_command::parse(&text);
 
-                        let (youtube_dl, url) = find_song(&self.ctx, &search).await?;
+        self.captions.add(Some(user_id), &name, &text).await;
 
-                        info!("Queueing {}", url);
+        // Chat messages are recorded with their reply, after it's generated.
+        if !matches!(command, Some(VoiceCommand::Chat(_))) {
+            self.bot.add_voice_history(&name, &text);
+        }
 
-                        let (input, _) =
-                            self.gen_audio(&format!("Queueing up, {}", &search)).await?;
-                        let _ = handler.play_input(input).set_volume(0.5);
+        let Some(command) = command else {
+            return Ok(());
+        };
 
-                        let handle = handler.enqueue_input(youtube_dl.into()).await;
-                        let _ = handle.set_volume(0.05);
-                    }
-                }
-                t if t.starts_with("stop") => {
-                    let manager = songbird::get(&self.ctx).await.unwrap().clone();
+        info!("Voice command: {:?}", command);
 
-                    if let Some(handler_lock) = manager.get(self.guild_id) {
-                        let mut handler = handler_lock.lock().await;
-                        let _ = handler.stop();
+        let manager = songbird::get(&self.ctx).await.unwrap().clone();
+        let Some(handler_lock) = manager.get(self.guild_id) else {
+            return Ok(());
+        };
+        let user = SerenityUserId::new(user_id);
 
-                        let queue = handler.queue();
-                        queue.stop();
+        match command {
+            VoiceCommand::Play(search) => self.play(&handler_lock, user_id, &search).await?,
+            VoiceCommand::Skip => {
+                let outcome = vote_skip(&self.ctx, self.guild_id, user).await;
+                self.say(&handler_lock, &outcome.describe()).await?;
+            }
+            VoiceCommand::Pause => {
+                let _ = handler_lock.lock().await.queue().pause();
+            }
+            VoiceCommand::Resume => {
+                let _ = handler_lock.lock().await.queue().resume();
+            }
+            VoiceCommand::Stop => {
+                if !is_dj(&self.ctx, self.guild_id, user).await {
+                    self.say(&handler_lock, "Only DJs can stop the music")
+                        .await?;
+                    return Ok(());
+                }
 
-                        let (input, _) = self
-                            .gen_audio("Just say the word and I'll be back to play some tunes")
-                            .await?;
-                        let _ = handler.play_input(input).set_volume(0.5);
-                    }
+                let (input, _) = self
+                    .gen_audio("Just say the word and I'll be back to play some tunes")
+                    .await?;
+
+                let mut handler = handler_lock.lock().await;
+                handler.stop();
+                handler.queue().stop();
+                speak(&self.ctx, self.guild_id, &mut handler, input).await;
+            }
+            VoiceCommand::VolumeUp | VoiceCommand::VolumeDown => {
+                if !is_dj(&self.ctx, self.guild_id, user).await {
+                    self.say(&handler_lock, "Only DJs can change the volume")
+                        .await?;
+                    return Ok(());
                 }
-                t if mentioned => {
-                    let res = self.gen_response(&t).await?;
-                    let (input, duration) = self.gen_audio(&res).await?;
-                    self.play_audio(input, duration).await?;
+
+                let step = if command == VoiceCommand::VolumeUp {
+                    VOICE_VOLUME_STEP
+                } else {
+                    -VOICE_VOLUME_STEP
+                };
+                let volume = get_guild_store(&self.ctx)
+                    .await
+                    .get(self.guild_id.get())
+                    .volume;
+                let volume = (volume + step).clamp(0.0, 2.0);
+
+                set_volume(&self.ctx, self.guild_id, volume).await;
+                self.say(
+                    &handler_lock,
+                    &format!("Volume {:.0} percent", volume * 100.0),
+                )
+                .await?;
+            }
+            VoiceCommand::NowPlaying => {
+                let current = handler_lock.lock().await.queue().current();
+                let track = match current {
+                    Some(handle) => handle.typemap().read().await.get::<TrackInfoKey>().cloned(),
+                    None => None,
+                };
+
+                let text = match track {
+                    Some(track) => format!("This is {}", track.display()),
+                    None => "Nothing's playing".to_string(),
+                };
+                self.say(&handler_lock, &text).await?;
+            }
+            VoiceCommand::Leave => {
+                if !is_dj(&self.ctx, self.guild_id, user).await {
+                    self.say(&handler_lock, "Only DJs can send me away").await?;
+                    return Ok(());
                 }
-                _ => {}
+
+                leave_voice(&self.ctx, self.guild_id).await;
             }
-        }
+            VoiceCommand::ClearQueue => {
+                if !is_dj(&self.ctx, self.guild_id, user).await {
+                    self.say(&handler_lock, "Only DJs can clear the queue")
+                        .await?;
+                    return Ok(());
+                }
 
-        Ok(())
-    }
+                let removed = {
+                    let handler = handler_lock.lock().await;
+                    clear_upcoming(&handler)
+                };
+                self.say(&handler_lock, &format!("Removed {} tracks", removed))
+                    .await?;
+                save_session(&self.ctx.data, self.guild_id).await;
+            }
+            VoiceCommand::Sound(spoken) => {
+                if let Some(name) = find_spoken_clip(self.guild_id, &spoken) {
+                    let mut handler = handler_lock.lock().await;
+                    play_clip(&self.ctx, self.guild_id, &mut handler, &name).await;
+                }
+            }
+            VoiceCommand::Chat(text) => {
+                let res = self.bot.gen_reply(&name, &text, SYS_PROMPT).await?;
+                info!("Response: {:?}", res);
 
-    fn save(&self, pcm_samples: &[i16], filename: &str) {
-        let spec = WavSpec {
-            channels: 2,
-            sample_rate: 48000,
-            bits_per_sample: 16,
-            sample_format: SampleFormat::Int,
-        };
+                self.bot.add_voice_history(&name, &text);
+                let (input, duration) = self.gen_audio(&res).await?;
+                self.play_audio(input, duration).await?;
 
-        let _ = fs::create_dir_all("cache");
-        let mut writer = WavWriter::create(filename, spec).unwrap();
+                // Only replies that were heard, short notices would crowd out the conversation.
+                self.bot.add_voice_history("adam", &res);
 
-        for &sample in pcm_samples {
-            let _ = writer.write_sample(sample);
+                let bot_name = self.ctx.cache.current_user().name.clone();
+                self.captions.add(None, &bot_name, &res).await;
+            }
         }
 
-        let _ = writer.finalize();
+        Ok(())
     }
 
-    async fn transcribe(&self, filename: &str) -> Result<String, Error> {
-        let file = fs::read(&filename)?;
-        let form = Form::new()
-            .part(
-                "file",
-                Part::bytes(file)
-                    .file_name(filename.to_string())
-                    .mime_str("audio/wav")
-                    .unwrap(),
-            )
-            .part("model", Part::text("whisper-1"));
+    /// Looks up and queues a spoken play request.
+    async fn play(
+        &self,
+        handler_lock: &Arc<tokio::sync::Mutex<Call>>,
+        user_id: u64,
+        search: &str,
+    ) -> Result<(), Error> {
+        info!("Searching for {}", search);
+
+        let found = match check_search(&self.ctx, search).await {
+            Ok(()) => find_song(&self.ctx, search, user_id).await?,
+            Err(rejection) => {
+                self.say(handler_lock, &rejection.describe()).await?;
+                return Ok(());
+            }
+        };
 
-        let res = self
-            .multipart_client
-            .post(format!("{OPENAI_API_URL}/audio/transcriptions"))
-            .multipart(form)
-            .send()
+        let Some(mut track) = found else {
+            self.say(
+                handler_lock,
+                &format!("Couldn't find anything for {}", search),
+            )
             .await?;
+            return Ok(());
+        };
 
-        let data = res.json::<serde_json::Value>().await?;
-        if let Some(text) = data["text"].as_str() {
-            info!("Transcription: {:?}", text);
-            return Ok(text.to_string());
+        if let Err(rejection) = check_content(&self.ctx, &mut track).await {
+            self.say(handler_lock, &rejection.describe()).await?;
+            return Ok(());
         }
 
-        Err(Error::msg("Failed to transcribe audio"))
-    }
+        info!("Queueing {}", track.url());
 
-    async fn gen_response(&self, text: &str) -> Result<String, Error> {
-        let data = self
-            .json_client
-            .post(format!("{OPENAI_API_URL}/chat/completions"))
-            .json(&ChatRequest {
-                model: self.chat_model.clone(),
-                messages: vec![
-                    ChatMessage::new("system", &SYS_PROMPT),
-                    ChatMessage::new("user", &text),
-                ],
-            })
-            .send()
-            .await?
-            .json::<serde_json::Value>()
+        let (input, _) = self
+            .gen_audio(&format!("Queueing up, {}", &track.title))
             .await?;
 
-        let res = data["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("idk")
-            .to_string();
+        let queued = {
+            let mut handler = handler_lock.lock().await;
+            let room = check_queue(&handler, track.requester).await;
 
-        info!("Response: {:?}", res);
+            if room.is_ok() {
+                speak(&self.ctx, self.guild_id, &mut handler, input).await;
+                enqueue(&self.ctx, self.guild_id, &mut handler, track).await;
+            }
+            room
+        };
 
-        Ok(res)
+        if let Err(rejection) = queued {
+            self.say(handler_lock, &rejection.describe()).await?;
+            return Ok(());
+        }
+
+        save_session(&self.ctx.data, self.guild_id).await;
+
+        Ok(())
     }
 
-    async fn gen_audio(&self, text: &str) -> Result<(Input, u64), Error> {
-        let res = self
-            .json_client
-            .post(format!("{OPENAI_API_URL}/audio/speech"))
-            .json(&SpeechRequest {
-                model: "tts-1".to_string(),
-                input: text.to_string(),
-                voice: "onyx".to_string(),
-            })
-            .send()
-            .await?;
+    /// Ignores speech while the bot is replying, unless it was interrupted.
+    fn should_process(&self, samples: &[i16]) -> bool {
+        let Ok(mut last_reply) = self.last_reply.lock() else {
+            return true;
+        };
 
-        if !res.status().is_success() {
-            return Err(Error::msg("Failed to generate audio"));
+        match last_reply.take() {
+            Some(reply) if reply.interrupted => true,
+            Some(reply) if reply.playing() => {
+                *last_reply = Some(reply);
+                false
+            }
+            // Short sounds right after a reply are usually its echo.
+            Some(_) => samples.len() >= 48000,
+            None => true,
         }
+    }
 
-        let bytes = res.bytes().await?;
+    async fn transcribe(&self, wav: Vec<u8>) -> Result<String, Error> {
+        let options = TranscribeOptions {
+            language: STT_LANGUAGE.map(str::to_string),
+            prompt: STT_PROMPT.map(str::to_string),
+        };
 
-        let mut input: Input = bytes.clone().into();
-        input = input.make_playable_async(&CODEC_REGISTRY, &PROBE).await?;
+        let text = self.bot.stt.transcribe(wav, &options).await?;
+        info!("Transcription: {:?}", text);
 
-        let duration = (bytes.len() / 48) as u64;
+        Ok(text)
+    }
+
+    /// Speaks `text` in the guild's voice, returning the audio and its length.
+    async fn gen_audio(&self, text: &str) -> Result<(Input, Duration), Error> {
+        let settings = get_guild_store(&self.ctx)
+            .await
+            .get(self.guild_id.get())
+            .tts;
+        let bytes = self.bot.tts.synthesize(text, &settings).await?;
+
+        let decoded = bytes.clone();
+        let extension = settings.extension().to_string();
+        let secs = tokio::task::spawn_blocking(move || {
+            decoded_duration(decoded, &extension, f64::INFINITY)
+        })
+        .await??;
+        let duration = Duration::milliseconds((secs * 1000.0) as i64);
+
+        let mut input: Input = bytes.into();
+        input = input.make_playable_async(&CODEC_REGISTRY, &PROBE).await?;
 
         if !input.is_playable() {
             return Err(Error::msg("Generated audio is not playable"));
@@ -263,25 +502,82 @@ impl Receiver {
         Ok((input, duration))
     }
 
-    async fn play_audio(&self, input: Input, duration: u64) -> Result<(), Error> {
+    /// Speaks a short notice over the music.
+    async fn say(
+        &self,
+        handler_lock: &Arc<tokio::sync::Mutex<Call>>,
+        text: &str,
+    ) -> Result<(), Error> {
+        let (input, _) = self.gen_audio(text).await?;
+        let mut handler = handler_lock.lock().await;
+        speak(&self.ctx, self.guild_id, &mut handler, input).await;
+
+        Ok(())
+    }
+
+    async fn play_audio(&mut self, input: Input, duration: Duration) -> Result<(), Error> {
         let manager = songbird::get(&self.ctx).await.unwrap();
 
-        if let Some(handler_lock) = manager.get(self.guild_id.clone()) {
-            let mut handler = handler_lock.lock().await;
-            let _ = handler.play_input(input).set_volume(0.5);
+        let Some(handler_lock) = manager.get(self.guild_id) else {
+            return Err(Error::msg("Not connected to voice"));
+        };
 
-            if let Ok(mut last_reply) = self.controller.last_reply.lock() {
-                *last_reply = Some(VoiceReply {
-                    timestamp: Utc::now(),
-                    duration: Duration::milliseconds(duration as i64),
-                });
-            }
+        let mut handler = handler_lock.lock().await;
+        let handle = speak(&self.ctx, self.guild_id, &mut handler, input).await;
+        let playing = Arc::new(AtomicBool::new(true));
+        let _ = handle.add_event(Event::Track(TrackEvent::End), ReplyEnded(playing.clone()));
+        let _ = handle.add_event(Event::Track(TrackEvent::Error), ReplyEnded(playing.clone()));
+
+        if let Ok(mut last_reply) = self.last_reply.lock() {
+            *last_reply = Some(VoiceReply {
+                handle,
+                playing,
+                expires: Utc::now() + duration + Duration::seconds(REPLY_GRACE_SECS),
+                interrupted: false,
+            });
         }
 
         Ok(())
     }
 }
 
+/// Downmixes 48kHz stereo voice to 16kHz mono, all Whisper needs, and
+/// wraps it in a WAV header.
+fn encode_utterance(samples: &[i16]) -> Result<Vec<u8>, Error> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: TRANSCRIBE_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut wav, spec)?;
+
+    // Averaging each run of 3 frames doubles as a crude low-pass before decimating.
+    for frames in samples.chunks(2 * DOWNSAMPLE_FACTOR) {
+        let sum = frames.iter().map(|&s| s as i32).sum::<i32>();
+        writer.write_sample((sum / frames.len() as i32) as i16)?;
+    }
+
+    writer.finalize()?;
+
+    Ok(wav.into_inner())
+}
+
+fn record(user_id: u64, wav: &[u8]) {
+    let filename = format!(
+        "{}/{}_{}.wav",
+        RECORDINGS_DIR,
+        user_id,
+        Utc::now().timestamp_millis()
+    );
+
+    if let Err(e) = fs::create_dir_all(RECORDINGS_DIR).and_then(|_| fs::write(&filename, wav)) {
+        warn!("Failed to record {}: {:?}", filename, e);
+    }
+}
+
 #[async_trait]
 impl EventHandler for Receiver {
     async fn act(&self, ctx: &Ctx<'_>) -> Option<Event> {
@@ -296,57 +592,70 @@ impl EventHandler for Receiver {
 
                 self.controller.known_ssrcs.insert(*ssrc, *user_id);
 
-                self.controller.accumulator.entry(*ssrc).or_insert(Slice {
-                    user_id: user_id.0,
-                    bytes: Vec::new(),
-                    timestamp: Utc::now(),
-                });
+                self.controller
+                    .speakers
+                    .entry(*ssrc)
+                    .or_insert_with(|| Speaker::new(user_id.0));
             }
             Ctx::VoiceTick(tick) => {
-                let speaking = tick.speaking.len();
-                let last_tick_was_empty =
-                    self.controller.last_tick_was_empty.load(Ordering::SeqCst);
-
-                if speaking == 0 && !last_tick_was_empty {
-                    self.controller
-                        .last_tick_was_empty
-                        .store(true, Ordering::SeqCst);
-
-                    for mut slice in self.controller.accumulator.iter_mut() {
-                        if slice.bytes.is_empty() {
-                            continue;
-                        }
-                        if let Err(e) = self.process(&mut slice).await {
-                            info!("Processing error: {:?}", e);
-                        }
+                let mut utterances = Vec::new();
+                let mut longest_speech = 0;
+
+                for (ssrc, data) in &tick.speaking {
+                    let Some(decoded_voice) = data.decoded_voice.as_ref() else {
+                        continue;
+                    };
+                    let Some(user_id) = self.controller.known_ssrcs.get(ssrc).map(|u| u.0) else {
+                        continue;
+                    };
+
+                    let mut speaker = self
+                        .controller
+                        .speakers
+                        .entry(*ssrc)
+                        .or_insert_with(|| Speaker::new(user_id));
+
+                    if let Some(samples) = speaker.segmenter.push(decoded_voice) {
+                        utterances.push(Utterance {
+                            user_id: speaker.user_id,
+                            samples,
+                        });
+                    }
+
+                    longest_speech = longest_speech.max(speaker.segmenter.speech_ms());
+                }
+
+                if longest_speech >= BARGE_IN_MS {
+                    self.barge_in().await;
+                }
+
+                // Speakers who sent nothing this tick are quiet for its 20ms.
+                for mut speaker in self.controller.speakers.iter_mut() {
+                    if tick.speaking.contains_key(speaker.key()) {
+                        continue;
                     }
-                } else if speaking != 0 {
-                    self.controller
-                        .last_tick_was_empty
-                        .store(false, Ordering::SeqCst);
-
-                    for (ssrc, data) in &tick.speaking {
-                        if let Some(decoded_voice) = data.decoded_voice.as_ref() {
-                            let mut bytes = decoded_voice.to_owned();
-
-                            if let Some(mut slice) = self.controller.accumulator.get_mut(&ssrc) {
-                                slice.bytes.append(&mut bytes);
-                            } else if let Some(user_id) = self.controller.known_ssrcs.get(ssrc) {
-                                self.controller.accumulator.insert(
-                                    *ssrc,
-                                    Slice {
-                                        user_id: user_id.0,
-                                        bytes,
-                                        timestamp: Utc::now(),
-                                    },
-                                );
-                            }
-                        }
+
+                    if let Some(samples) = speaker.segmenter.push_silence(TICK_MS) {
+                        utterances.push(Utterance {
+                            user_id: speaker.user_id,
+                            samples,
+                        });
                     }
                 }
+
+                for utterance in utterances {
+                    self.submit(utterance);
+                }
             }
             Ctx::ClientDisconnect(ClientDisconnect { user_id, .. }) => {
                 info!("{:?} disconnected", user_id);
+
+                self.controller
+                    .known_ssrcs
+                    .retain(|_, known| known != user_id);
+                self.controller
+                    .speakers
+                    .retain(|_, speaker| speaker.user_id != user_id.0);
             }
             _ => {}
         }
@@ -355,57 +664,153 @@ impl EventHandler for Receiver {
     }
 }
 
+/// Lets people cut the bot off by talking over it.
+#[command]
+#[only_in(guilds)]
+#[aliases("barge-in")]
+pub async fn bargein(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let store = get_guild_store(ctx).await;
+
+    let enabled = match args.message().trim() {
+        "on" => true,
+        "off" => false,
+        "" => !store.get(guild_id.get()).barge_in,
+        _ => {
+            let _ = msg
+                .channel_id
+                .say(&ctx.http, "Usage: ~bargein [on|off]")
+                .await;
+            return Ok(());
+        }
+    };
+
+    store.update(guild_id.get(), |s| s.barge_in = enabled);
+
+    let text = if enabled {
+        "Barge-in enabled, talking over me cuts me off."
+    } else {
+        "Barge-in disabled, I'll finish what I'm saying."
+    };
+    let _ = msg.channel_id.say(&ctx.http, text).await;
+
+    Ok(())
+}
+
 impl Bot {
     pub async fn join_channel(&self, ctx: &Context, msg: &Message) {
-        if msg.guild_id.is_none() {
-            self.send_msg(&ctx, &msg, "no").await;
+        let Some(guild_id) = msg.guild_id else {
+            self.send_msg(ctx, msg, "no").await;
             return;
+        };
+
+        if let Some(channel_id) = user_voice_channel(ctx, guild_id, msg.author.id) {
+            join_voice(ctx, guild_id, channel_id).await;
+            offer_session(ctx, guild_id, msg.channel_id).await;
         }
+    }
 
-        let (guild_id, channel_id) = {
-            let guild = msg.guild(&ctx.cache).unwrap();
-            let channel_id = guild
-                .voice_states
-                .get(&msg.author.id)
-                .and_then(|voice_state| voice_state.channel_id);
-            (guild.id, channel_id)
+    pub async fn leave_channel(&self, ctx: &Context, msg: &Message) {
+        let Some(guild_id) = msg.guild_id else {
+            self.send_msg(ctx, msg, "no").await;
+            return;
         };
 
-        if let Some(channel_id) = channel_id {
-            info!("Joining voice channel");
+        leave_voice(ctx, guild_id).await;
+    }
+}
 
-            ctx.set_activity(Some(ActivityData::listening("youtube music")));
+pub fn user_voice_channel(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: SerenityUserId,
+) -> Option<ChannelId> {
+    let guild = ctx.cache.guild(guild_id)?;
+
+    guild
+        .voice_states
+        .get(&user_id)
+        .and_then(|voice_state| voice_state.channel_id)
+}
 
-            let manager = songbird::get(&ctx).await.unwrap().clone();
+/// A member's nickname in the guild, falling back to their username.
+pub fn display_name(ctx: &Context, guild_id: GuildId, user_id: SerenityUserId) -> String {
+    let from_guild = ctx.cache.guild(guild_id).and_then(|guild| {
+        let member = guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|state| state.member.as_ref())
+            .or_else(|| guild.members.get(&user_id))?;
+
+        Some(member.display_name().to_string())
+    });
+
+    from_guild
+        .or_else(|| ctx.cache.user(user_id).map(|user| user.name.clone()))
+        .unwrap_or_else(|| user_id.to_string())
+}
 
-            if let Ok(handler_lock) = manager.join(guild_id, channel_id).await {
-                let mut handler = handler_lock.lock().await;
+/// Humans connected to a voice channel, ignoring bots.
+pub fn channel_listeners(ctx: &Context, guild_id: GuildId, channel_id: u64) -> Vec<SerenityUserId> {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return Vec::new();
+    };
+
+    guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id.map(|c| c.get()) == Some(channel_id))
+        .filter(|state| state.user_id != BOT_ID)
+        .filter(|state| !state.member.as_ref().map(|m| m.user.bot).unwrap_or(false))
+        .map(|state| state.user_id)
+        .collect()
+}
 
-                let receiver = Receiver::new(ctx.to_owned(), guild_id.into());
+/// Joins a voice channel, listening for speech and leaving again when idle.
+pub async fn join_voice(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<Arc<tokio::sync::Mutex<Call>>> {
+    info!("Joining voice channel");
 
-                handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), receiver.clone());
-                handler.add_global_event(CoreEvent::VoiceTick.into(), receiver.clone());
-                handler.add_global_event(CoreEvent::ClientDisconnect.into(), receiver);
-            }
-        }
+    ctx.set_activity(Some(ActivityData::listening("youtube music")));
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    let handler_lock = manager.join(guild_id, channel_id).await.ok()?;
+
+    {
+        let mut handler = handler_lock.lock().await;
+
+        let receiver = Receiver::new(ctx.to_owned(), guild_id, get_bot(ctx).await);
+        let watchdog = Watchdog::new(ctx.to_owned(), guild_id);
+
+        handler.remove_all_global_events();
+        handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), receiver.clone());
+        handler.add_global_event(CoreEvent::VoiceTick.into(), receiver.clone());
+        handler.add_global_event(CoreEvent::ClientDisconnect.into(), receiver);
+        handler.add_global_event(CoreEvent::VoiceTick.into(), watchdog.clone());
+        handler.add_global_event(
+            Event::Periodic(StdDuration::from_secs(WATCHDOG_INTERVAL_SECS), None),
+            watchdog,
+        );
     }
 
-    pub async fn leave_channel(&self, ctx: &Context, msg: &Message) {
-        if msg.guild_id.is_none() {
-            self.send_msg(&ctx, &msg, "no").await;
-            return;
-        }
+    Some(handler_lock)
+}
 
-        ctx.set_activity(None);
+pub async fn leave_voice(ctx: &Context, guild_id: GuildId) {
+    save_session(&ctx.data, guild_id).await;
 
-        let guild_id = msg.guild_id.unwrap();
-        let manager = songbird::get(&ctx).await.unwrap().clone();
+    ctx.set_activity(None);
 
-        if manager.get(guild_id).is_some() {
-            info!("Leaving voice channel");
-            let _ = manager.remove(guild_id).await;
-        }
+    let manager = songbird::get(ctx).await.unwrap().clone();
 
-        let _ = fs::remove_dir_all("cache");
+    if manager.get(guild_id).is_some() {
+        info!("Leaving voice channel");
+        let _ = manager.remove(guild_id).await;
     }
+
+    get_ducking(ctx, guild_id).await.reset();
 }
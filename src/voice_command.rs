@@ -0,0 +1,409 @@
+use crate::cfg::WAKE_WORDS;
+
+/// What someone asked for out loud.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoiceCommand {
+    Play(String),
+    Skip,
+    Pause,
+    Resume,
+    Stop,
+    VolumeUp,
+    VolumeDown,
+    NowPlaying,
+    Leave,
+    ClearQueue,
+    Sound(String),
+    /// Anything addressed to the bot that isn't a command.
+    Chat(String),
+}
+
+/// Fixed phrases, including ways Whisper tends to mishear them.
+const PHRASES: &[(&str, VoiceCommand)] = &[
+    ("skip", VoiceCommand::Skip),
+    ("skip it", VoiceCommand::Skip),
+    ("skip this", VoiceCommand::Skip),
+    ("skip song", VoiceCommand::Skip),
+    ("skip this song", VoiceCommand::Skip),
+    ("skip the song", VoiceCommand::Skip),
+    ("next", VoiceCommand::Skip),
+    ("next song", VoiceCommand::Skip),
+    ("next track", VoiceCommand::Skip),
+    ("skipped", VoiceCommand::Skip),
+    ("ship", VoiceCommand::Skip),
+    ("pause", VoiceCommand::Pause),
+    ("pause the music", VoiceCommand::Pause),
+    ("pause it", VoiceCommand::Pause),
+    ("paws", VoiceCommand::Pause),
+    ("pose", VoiceCommand::Pause),
+    ("pours", VoiceCommand::Pause),
+    ("resume", VoiceCommand::Resume),
+    ("resume the music", VoiceCommand::Resume),
+    ("unpause", VoiceCommand::Resume),
+    ("continue", VoiceCommand::Resume),
+    ("keep playing", VoiceCommand::Resume),
+    ("presume", VoiceCommand::Resume),
+    ("stop", VoiceCommand::Stop),
+    ("stop it", VoiceCommand::Stop),
+    ("stop music", VoiceCommand::Stop),
+    ("stop the music", VoiceCommand::Stop),
+    ("stop playing", VoiceCommand::Stop),
+    ("volume up", VoiceCommand::VolumeUp),
+    ("turn it up", VoiceCommand::VolumeUp),
+    ("turn up", VoiceCommand::VolumeUp),
+    ("turn the music up", VoiceCommand::VolumeUp),
+    ("louder", VoiceCommand::VolumeUp),
+    ("volume down", VoiceCommand::VolumeDown),
+    ("turn it down", VoiceCommand::VolumeDown),
+    ("turn down", VoiceCommand::VolumeDown),
+    ("turn the music down", VoiceCommand::VolumeDown),
+    ("quieter", VoiceCommand::VolumeDown),
+    ("softer", VoiceCommand::VolumeDown),
+    ("whats playing", VoiceCommand::NowPlaying),
+    ("what is playing", VoiceCommand::NowPlaying),
+    ("whats this song", VoiceCommand::NowPlaying),
+    ("what is this song", VoiceCommand::NowPlaying),
+    ("what song is this", VoiceCommand::NowPlaying),
+    ("what song is playing", VoiceCommand::NowPlaying),
+    ("now playing", VoiceCommand::NowPlaying),
+    ("leave", VoiceCommand::Leave),
+    ("go away", VoiceCommand::Leave),
+    ("get out", VoiceCommand::Leave),
+    ("disconnect", VoiceCommand::Leave),
+    ("bye", VoiceCommand::Leave),
+    ("clear", VoiceCommand::ClearQueue),
+    ("clear queue", VoiceCommand::ClearQueue),
+    ("clear the queue", VoiceCommand::ClearQueue),
+    ("empty the queue", VoiceCommand::ClearQueue),
+];
+
+/// Words that start a play request, "clay" and "blay" being common mishearings.
+const PLAY_WORDS: &[&str] = &["play", "clay", "blay"];
+/// Play words too common in conversation to count without the wake word.
+const ADDRESSED_PLAY_WORDS: &[&str] = &["plays", "played", "lay", "put on"];
+const SOUND_WORDS: &[&str] = &["soundboard", "sound board"];
+const ADDRESSED_SOUND_WORDS: &[&str] = &["sound"];
+/// Politeness and filler before or after the actual command.
+const FILLER: &[&str] = &[
+    "hey",
+    "hi",
+    "ok",
+    "okay",
+    "so",
+    "um",
+    "uh",
+    "please",
+    "can you",
+    "could you",
+    "would you",
+    "will you",
+];
+
+/// Lowercases and strips punctuation, "What's playing?" becoming `["whats", "playing"]`.
+fn normalize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .replace('\'', "")
+        .replace(|c: char| !c.is_alphanumeric(), " ")
+        .split_whitespace()
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Length of `phrase` if `words` start with it.
+fn starts_with(words: &[String], phrase: &str) -> Option<usize> {
+    let phrase = phrase.split_whitespace().collect::<Vec<_>>();
+
+    let matches = words.len() >= phrase.len() && words.iter().zip(&phrase).all(|(a, b)| a == b);
+
+    matches.then_some(phrase.len())
+}
+
+fn strip_filler(mut words: &[String]) -> &[String] {
+    loop {
+        let skip = FILLER
+            .iter()
+            .find_map(|filler| starts_with(words, filler))
+            .unwrap_or(0);
+
+        if skip == 0 {
+            break;
+        }
+        words = &words[skip..];
+    }
+
+    while words.last().map(|w| w == "please" || w == "now") == Some(true) {
+        words = &words[..words.len() - 1];
+    }
+
+    words
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// American Soundex, "adam" and "atom" both being `A350`.
+fn soundex(word: &str) -> String {
+    fn code(c: char) -> Option<char> {
+        match c {
+            'b' | 'f' | 'p' | 'v' => Some('1'),
+            'c' | 'g' | 'j' | 'k' | 'q' | 's' | 'x' | 'z' => Some('2'),
+            'd' | 't' => Some('3'),
+            'l' => Some('4'),
+            'm' | 'n' => Some('5'),
+            'r' => Some('6'),
+            _ => None,
+        }
+    }
+
+    let mut letters = word.chars().filter(|c| c.is_ascii_alphabetic());
+    let Some(first) = letters.next() else {
+        return String::new();
+    };
+
+    let mut key = first.to_ascii_uppercase().to_string();
+    let mut last = code(first);
+
+    for c in letters {
+        let current = code(c);
+        if let Some(digit) = current {
+            if current != last {
+                key.push(digit);
+            }
+        }
+        // "h" and "w" don't separate letters with the same code, vowels do.
+        if c != 'h' && c != 'w' {
+            last = current;
+        }
+        if key.len() == 4 {
+            break;
+        }
+    }
+
+    format!("{:0<4}", key)
+}
+
+/// Whether a heard word sounds like a wake word. Short aliases have to match
+/// exactly, anything else may be one letter off or, if `phonetic`, sound the same.
+fn sounds_like(heard: &str, alias: &str, phonetic: bool) -> bool {
+    if heard == alias {
+        return true;
+    }
+
+    if alias.len() < 4 || heard.len().abs_diff(alias.len()) > 2 {
+        return false;
+    }
+
+    levenshtein(heard, alias) <= 1 || (phonetic && soundex(heard) == soundex(alias))
+}
+
+/// Where in `words` a wake word was said, as a range of word indices.
+fn find_wake_word(words: &[String]) -> Option<(usize, usize)> {
+    let aliases = WAKE_WORDS
+        .iter()
+        .map(|alias| normalize(alias).concat())
+        .collect::<Vec<_>>();
+
+    (0..words.len()).find_map(|start| {
+        // Wake words can be heard as two words, "a dam" or "i dont". Those
+        // aren't compared phonetically, "at him" sounds like "adam" too.
+        [2, 1].into_iter().find_map(|len| {
+            let end = start + len;
+            let heard = words.get(start..end)?.concat();
+
+            aliases
+                .iter()
+                .any(|alias| sounds_like(&heard, alias, len == 1))
+                .then_some((start, end))
+        })
+    })
+}
+
+/// Length of the first of `triggers`, or if `addressed` of `loose`, that `words` start with.
+fn find_trigger(
+    words: &[String],
+    triggers: &[&str],
+    loose: &[&str],
+    addressed: bool,
+) -> Option<usize> {
+    let loose = if addressed { loose } else { &[] };
+
+    triggers
+        .iter()
+        .chain(loose)
+        .find_map(|trigger| starts_with(words, trigger))
+}
+
+/// Matches a command at the start of `words`, returning how many words it used.
+/// `addressed` is whether the wake word was said.
+fn parse_command(words: &[String], addressed: bool) -> Option<(VoiceCommand, usize)> {
+    if let Some(len) = find_trigger(words, PLAY_WORDS, ADDRESSED_PLAY_WORDS, addressed) {
+        let search = words[len..].join(" ");
+
+        return Some(if search.is_empty() {
+            (VoiceCommand::Resume, len)
+        } else {
+            (VoiceCommand::Play(search), words.len())
+        });
+    }
+
+    if let Some(len) = find_trigger(words, SOUND_WORDS, ADDRESSED_SOUND_WORDS, addressed) {
+        let name = words[len..].join(" ");
+
+        if !name.is_empty() {
+            return Some((VoiceCommand::Sound(name), words.len()));
+        }
+    }
+
+    PHRASES
+        .iter()
+        .filter_map(|(phrase, command)| Some((command, starts_with(words, phrase)?)))
+        .max_by_key(|(_, len)| *len)
+        .map(|(command, len)| (command.clone(), len))
+}
+
+/// Parses a transcript. Play and soundboard requests only need to start the
+/// utterance, though looser ways of phrasing them need the wake word. Other commands need the wake word and have to be all that was
+/// said (give or take filler), so "bye" or "next time" in conversation don't
+/// count. Anything else addressed to the bot is a chat message.
+pub fn parse(transcript: &str) -> Option<VoiceCommand> {
+    let words = normalize(transcript);
+    let wake_word = find_wake_word(&words);
+
+    let command_words = match wake_word {
+        // "play something adam" has the command before the wake word.
+        Some((start, end)) if end == words.len() => &words[..start],
+        Some((_, end)) => &words[end..],
+        None => &words[..],
+    };
+    let command_words = strip_filler(command_words);
+
+    match parse_command(command_words, wake_word.is_some()) {
+        Some((command @ (VoiceCommand::Play(_) | VoiceCommand::Sound(_)), _)) => Some(command),
+        Some((command, len)) if wake_word.is_some() && len == command_words.len() => Some(command),
+        _ if wake_word.is_some() => Some(VoiceCommand::Chat(transcript.trim().to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(search: &str) -> Option<VoiceCommand> {
+        Some(VoiceCommand::Play(search.to_string()))
+    }
+
+    #[test]
+    fn wake_word_mishearings() {
+        for transcript in ["A dam, skip.", "Atom, skip.", "Adams skip", "Adam skip"] {
+            assert_eq!(
+                parse(transcript),
+                Some(VoiceCommand::Skip),
+                "{}",
+                transcript
+            );
+        }
+    }
+
+    #[test]
+    fn play_mishearings() {
+        for word in ["Play", "Clay", "Blay"] {
+            let transcript = format!("{} despacito.", word);
+            assert_eq!(parse(&transcript), play("despacito"), "{}", transcript);
+        }
+    }
+
+    #[test]
+    fn loose_play_words_need_wake_word() {
+        for word in ["lay", "played", "plays", "put on"] {
+            let transcript = format!("Adam, {} despacito.", word);
+            assert_eq!(parse(&transcript), play("despacito"), "{}", transcript);
+        }
+        assert_eq!(
+            parse("Adam, sound airhorn"),
+            Some(VoiceCommand::Sound("airhorn".to_string()))
+        );
+    }
+
+    #[test]
+    fn loose_play_words_in_conversation_dont_match() {
+        assert_eq!(parse("played it yesterday"), None);
+        assert_eq!(parse("plays really well on the new patch"), None);
+        assert_eq!(parse("lay it on the table"), None);
+        assert_eq!(parse("put on your headphones"), None);
+        assert_eq!(parse("sound good to me"), None);
+    }
+
+    #[test]
+    fn command_mishearings() {
+        for word in ["paws", "pose", "pours"] {
+            assert_eq!(parse(&format!("adam {}", word)), Some(VoiceCommand::Pause));
+        }
+        assert_eq!(parse("Adam, presume."), Some(VoiceCommand::Resume));
+        assert_eq!(parse("Adam, ship."), Some(VoiceCommand::Skip));
+    }
+
+    #[test]
+    fn wake_word_at_the_end() {
+        assert_eq!(parse("play despacito adam"), play("despacito"));
+        assert_eq!(parse("skip please, Adam"), Some(VoiceCommand::Skip));
+    }
+
+    #[test]
+    fn filler_is_ignored() {
+        assert_eq!(
+            parse("Hey Adam, can you turn it up please?"),
+            Some(VoiceCommand::VolumeUp)
+        );
+        assert_eq!(
+            parse("Adam, what's playing?"),
+            Some(VoiceCommand::NowPlaying)
+        );
+    }
+
+    #[test]
+    fn similar_words_dont_wake() {
+        assert_eq!(parse("at him play x"), None);
+        assert_eq!(parse("look at him go"), None);
+    }
+
+    #[test]
+    fn commands_in_conversation_dont_match() {
+        assert_eq!(parse("skip"), None);
+        assert_eq!(parse("bye"), None);
+        assert_eq!(parse("next time we go out"), None);
+    }
+
+    #[test]
+    fn anything_else_for_the_bot_is_chat() {
+        assert_eq!(
+            parse("Adam, stop talking so much."),
+            Some(VoiceCommand::Chat(
+                "Adam, stop talking so much.".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("Adam what's the weather like"),
+            Some(VoiceCommand::Chat(
+                "Adam what's the weather like".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+use serenity::all::GuildId;
+use serenity::async_trait;
+use serenity::client::Context;
+use songbird::{Event, EventContext as Ctx, EventHandler};
+
+use crate::cfg::IDLE_TIMEOUT_SECS;
+use crate::voice::{channel_listeners, leave_voice};
+
+/// Leaves the voice channel once nobody's listening, or nothing's been
+/// playing or said for `IDLE_TIMEOUT_SECS`.
+#[derive(Clone)]
+pub struct Watchdog {
+    ctx: Context,
+    guild_id: GuildId,
+    last_active: Arc<Mutex<Instant>>,
+}
+
+impl Watchdog {
+    pub fn new(ctx: Context, guild_id: GuildId) -> Self {
+        Self {
+            ctx,
+            guild_id,
+            last_active: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn touch(&self) {
+        if let Ok(mut last_active) = self.last_active.lock() {
+            *last_active = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_active
+            .lock()
+            .map(|last_active| last_active.elapsed())
+            .unwrap_or_default()
+    }
+
+    async fn check(&self) -> bool {
+        let manager = songbird::get(&self.ctx).await.unwrap().clone();
+
+        let Some(handler_lock) = manager.get(self.guild_id) else {
+            return true;
+        };
+
+        let (channel_id, queue_empty) = {
+            let handler = handler_lock.lock().await;
+            (handler.current_channel(), handler.queue().is_empty())
+        };
+
+        let Some(channel_id) = channel_id else {
+            return false;
+        };
+
+        if channel_listeners(&self.ctx, self.guild_id, channel_id.0.get()).is_empty() {
+            info!("Nobody left in voice channel, leaving");
+            return true;
+        }
+
+        if !queue_empty {
+            self.touch();
+            return false;
+        }
+
+        if self.idle_for() >= Duration::from_secs(IDLE_TIMEOUT_SECS) {
+            info!("Idle for {}s, leaving voice channel", IDLE_TIMEOUT_SECS);
+            return true;
+        }
+
+        false
+    }
+}
+
+#[async_trait]
+impl EventHandler for Watchdog {
+    async fn act(&self, ctx: &Ctx<'_>) -> Option<Event> {
+        match ctx {
+            Ctx::VoiceTick(tick) if !tick.speaking.is_empty() => self.touch(),
+            Ctx::Track(_) if self.check().await => {
+                // Leaving tears down the driver running this handler.
+                let (ctx, guild_id) = (self.ctx.clone(), self.guild_id);
+                tokio::spawn(async move { leave_voice(&ctx, guild_id).await });
+                return Some(Event::Cancel);
+            }
+            _ => {}
+        }
+
+        None
+    }
+}